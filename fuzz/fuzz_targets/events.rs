@@ -0,0 +1,52 @@
+//! Any stream of events, however badly nested, must render without panicking
+//!
+//! Run with `cargo fuzz run events`: the first byte is the width, each of the
+//! others an event
+
+#![no_main]
+
+use catmark::pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Tag};
+use catmark::RenderOptions;
+use libfuzzer_sys::fuzz_target;
+
+fn tag(n: u8) -> Tag<'static> {
+    match n % 12 {
+        0 => Tag::Paragraph,
+        1 => Tag::Heading(HeadingLevel::H2, None, vec![]),
+        2 => Tag::BlockQuote,
+        3 => Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::Borrowed("rust"))),
+        4 => Tag::List(None),
+        5 => Tag::List(Some(u64::MAX)),
+        6 => Tag::Item,
+        7 => Tag::Emphasis,
+        8 => Tag::Strong,
+        9 => Tag::Link(LinkType::Inline, "http://x".into(), "".into()),
+        10 => Tag::FootnoteDefinition("f".into()),
+        _ => Tag::Image(LinkType::Inline, "i.png".into(), "t".into()),
+    }
+}
+
+fn event(n: u8) -> Event<'static> {
+    match n >> 4 {
+        0..=4 => Event::Start(tag(n)),
+        5..=9 => Event::End(tag(n)),
+        10 => Event::Text("some text\n".into()),
+        11 => Event::Code("code".into()),
+        12 => Event::SoftBreak,
+        13 => Event::HardBreak,
+        14 => Event::Rule,
+        _ => match n & 1 {
+            0 => Event::TaskListMarker(true),
+            _ => Event::FootnoteReference("f".into()),
+        },
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&width, events)) = data.split_first() else {
+        return;
+    };
+    let opts = RenderOptions::with_width(width.into());
+    let doc = catmark::render_events(events.iter().map(|&n| event(n)), &opts);
+    doc.render_to_string(None);
+});
@@ -0,0 +1,30 @@
+//! Any markdown at any width must render without panicking - see `events` for
+//! streams of events which may not come from markdown
+//!
+//! Run with `cargo fuzz run render`: the first two bytes are the width, the third
+//! one some options and the rest the markdown
+
+#![no_main]
+
+use catmark::{Document, RenderOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&[lo, hi, flags], text)) = data.split_first_chunk::<3>() else {
+        return;
+    };
+    let Ok(text) = std::str::from_utf8(text) else {
+        return;
+    };
+    let mut opts = RenderOptions::with_width(u16::from_le_bytes([lo, hi]));
+    opts.hyphenate = flags & 1 != 0;
+    opts.number_headings = flags & 2 != 0;
+    opts.math = flags & 4 != 0;
+    opts.highlight = flags & 8 != 0;
+    opts.smart_punctuation = flags & 16 != 0;
+    opts.margins.left = u16::from(flags >> 5);
+    opts.margins.right = u16::from(flags >> 5);
+    let doc = Document::with_options(text, &opts);
+    doc.render_to_string(None);
+    doc.render_to_string(Some(usize::from(flags)));
+});
@@ -4,372 +4,1739 @@
 
 //! ANSI renderer for pulldown-cmark.
 
-use crate::dombox::{split_at_in_place, BorderType, BoxKind, DomBox, DomColor, TermColor, XY};
-use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, Tag};
+#[cfg(feature = "syntax-highlighting")]
+use crate::dombox::Palette;
+use crate::dombox::{
+    split_at_in_place, BorderType, BoxKind, DomBox, DomColor, Edges, TermColor, TextAlign, XY,
+};
+use crate::math;
+use crate::trace;
+#[cfg(feature = "syntax-highlighting")]
+use crate::trace::Span;
+use crate::{LinkStyle, QuoteStyle, RenderOptions, RuleStyle};
+use pulldown_cmark::{Alignment, CodeBlockKind, CowStr, Event, Tag};
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "rayon")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "rayon")]
+use std::sync::{mpsc, Arc};
+#[cfg(feature = "syntax-highlighting")]
 use syntect::easy::HighlightLines;
+#[cfg(feature = "syntax-highlighting")]
 use syntect::highlighting;
+#[cfg(feature = "syntax-highlighting")]
 use syntect::parsing::syntax_definition::SyntaxDefinition;
+#[cfg(feature = "syntax-highlighting")]
 use syntect::parsing::SyntaxSet;
 
+// loading these is slow, so do it only once - syntect's sets can't be shared
+// between threads, hence one copy per thread, kept by the threads of the pool
+#[cfg(feature = "syntax-highlighting")]
+thread_local! {
+    static SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static THEMES: highlighting::ThemeSet = highlighting::ThemeSet::load_defaults();
+}
+
+/// The highlighted pieces of each line of a code block
+#[cfg(feature = "rayon")]
+type HighlightedLines<'a> = Vec<Vec<(highlighting::Style, &'a str)>>;
+
+/// A fenced code block to highlight on a worker thread: its language, its theme,
+/// its lines, whether a thread took it already and where to send the result
+#[cfg(feature = "rayon")]
+type HighlightJob<'a, 'b> = (
+    String,
+    &'b str,
+    Vec<&'a str>,
+    Arc<AtomicBool>,
+    mpsc::Sender<HighlightedLines<'a>>,
+);
+
+/// A code block given to the pool: whether a thread took it, and where its
+/// lines will come from
+#[cfg(feature = "rayon")]
+type PendingBlock<'a> = (Arc<AtomicBool>, mpsc::Receiver<HighlightedLines<'a>>);
+
 struct Ctx<'a, 'b, I> {
     iter: I,
+    /// Events to handle before going on with `iter`
+    replay: VecDeque<Event<'a>>,
+    opts: &'b RenderOptions<'b>,
     links: Option<DomBox<'a>>,
+    /// Link numbers by destination, in order of first appearance
+    link_numbers: HashMap<String, usize>,
     footnotes: Option<DomBox<'a>>,
+    /// Footnote numbers by name, in order of first appearance
+    footnote_numbers: HashMap<String, usize>,
+    /// Numbers of the footnotes in the footnotes box, in order
+    footnote_defs: Vec<usize>,
+    /// Titles of the sections referencing each footnote, by number
+    backlinks: HashMap<usize, Vec<String>>,
+    /// Title of the section we're in
+    section: Option<String>,
+    /// Content of the footnotes to render where they're referenced, by name
+    inline_footnotes: HashMap<String, Vec<Event<'a>>>,
+    /// Names of the inline footnotes being rendered, innermost last
+    expanding: Vec<CowStr<'a>>,
+    /// Number and title of the headings, by anchor
+    anchors: HashMap<String, (String, String)>,
+    /// Anchors and numbers of the headings still to be built, in order
+    heading_anchors: VecDeque<(String, String)>,
+    in_code_block: bool,
+    /// Alignments of the columns of the table we're in
+    table_aligns: Vec<Alignment>,
+    /// Row of the table we're in, the headings being the first, and column of
+    /// its next cell
+    table_cell: (usize, usize),
+    /// How many blockquotes we're in
+    quote_depth: usize,
+    /// How many unordered lists we're in
+    bullet_depth: usize,
+    #[cfg(feature = "syntax-highlighting")]
     syntaxes: &'b SyntaxSet,
+    #[cfg(feature = "syntax-highlighting")]
     themes: &'b highlighting::ThemeSet,
+    #[cfg(feature = "syntax-highlighting")]
     syntax: Option<&'b SyntaxDefinition>,
-    pub theme: &'b str,
+    #[cfg(feature = "syntax-highlighting")]
     highline: Option<HighlightLines<'b>>,
+    /// The syntax of the current code block is to be guessed from its first line
+    #[cfg(feature = "syntax-highlighting")]
+    detect_lang: bool,
+    /// Timing of the highlighting of the current code block
+    #[cfg(feature = "syntax-highlighting")]
+    highlighting: Option<Span>,
+    /// Code blocks being highlighted by other threads, by address of their first line
+    #[cfg(feature = "rayon")]
+    pending: HashMap<usize, PendingBlock<'a>>,
+    /// The lines of the current code block already highlighted, still to be added
+    #[cfg(feature = "rayon")]
+    ready: VecDeque<Vec<(highlighting::Style, &'a str)>>,
 }
 
 impl<'a, 'b, I: Iterator<Item = Event<'a>>> Ctx<'a, 'b, I> {
-    pub fn new(iter: I, syntaxes: &'b SyntaxSet, themes: &'b highlighting::ThemeSet) -> Self {
+    #[cfg(feature = "syntax-highlighting")]
+    pub fn new(
+        iter: I,
+        opts: &'b RenderOptions<'b>,
+        syntaxes: &'b SyntaxSet,
+        themes: &'b highlighting::ThemeSet,
+    ) -> Self {
         Ctx {
-            iter: iter,
+            iter,
+            replay: VecDeque::new(),
+            opts,
             links: None,
+            link_numbers: HashMap::new(),
             footnotes: None,
-            syntaxes: syntaxes,
-            themes: themes,
+            footnote_numbers: HashMap::new(),
+            footnote_defs: vec![],
+            backlinks: HashMap::new(),
+            section: None,
+            inline_footnotes: HashMap::new(),
+            expanding: vec![],
+            anchors: HashMap::new(),
+            heading_anchors: VecDeque::new(),
+            in_code_block: false,
+            table_aligns: vec![],
+            table_cell: (0, 0),
+            quote_depth: 0,
+            bullet_depth: 0,
+            syntaxes,
+            themes,
             syntax: None,
-            theme: "base16-eighties.dark",
             highline: None,
+            detect_lang: false,
+            highlighting: None,
+            #[cfg(feature = "rayon")]
+            pending: HashMap::new(),
+            #[cfg(feature = "rayon")]
+            ready: VecDeque::new(),
+        }
+    }
+    #[cfg(not(feature = "syntax-highlighting"))]
+    pub fn new(iter: I, opts: &'b RenderOptions<'b>) -> Self {
+        Ctx {
+            iter,
+            replay: VecDeque::new(),
+            opts,
+            links: None,
+            link_numbers: HashMap::new(),
+            footnotes: None,
+            footnote_numbers: HashMap::new(),
+            footnote_defs: vec![],
+            backlinks: HashMap::new(),
+            section: None,
+            inline_footnotes: HashMap::new(),
+            expanding: vec![],
+            anchors: HashMap::new(),
+            heading_anchors: VecDeque::new(),
+            in_code_block: false,
+            table_aligns: vec![],
+            table_cell: (0, 0),
+            quote_depth: 0,
+            bullet_depth: 0,
+        }
+    }
+    #[cfg(feature = "syntax-highlighting")]
+    fn start_highlighting(&mut self, info: &CodeBlockKind) {
+        if !self.opts.code_colors {
+            return;
         }
+        match info {
+            CodeBlockKind::Fenced(syn) => {
+                let syn = fence_lang(syn).to_lowercase();
+                let syn = self.opts.lang_aliases.get(&syn).unwrap_or(&syn);
+                self.highlight_with(self.syntaxes.find_syntax_by_token(syn));
+            }
+            CodeBlockKind::Indented => self.detect_lang = self.opts.detect_lang,
+        }
+    }
+    #[cfg(feature = "syntax-highlighting")]
+    fn highlight_with(&mut self, syntax: Option<&'b SyntaxDefinition>) {
+        self.syntax = syntax;
+        if let (Some(syn), Some(theme)) = (self.syntax, code_theme(self.themes, self.opts.theme)) {
+            self.highline = Some(HighlightLines::new(syn, theme));
+            self.highlighting = Some(trace::span!("highlight"));
+        }
+    }
+    #[cfg(not(feature = "syntax-highlighting"))]
+    fn start_highlighting(&mut self, _info: &CodeBlockKind) {}
+    #[cfg(feature = "syntax-highlighting")]
+    fn stop_highlighting(&mut self) {
+        self.highline = None;
+        self.syntax = None;
+        self.detect_lang = false;
+        self.highlighting = None;
+        #[cfg(feature = "rayon")]
+        self.ready.clear();
+    }
+    #[cfg(not(feature = "syntax-highlighting"))]
+    fn stop_highlighting(&mut self) {}
+    /// Number of a link destination, listing it at the end the first time
+    fn link_number(&mut self, dest: CowStr<'a>) -> usize {
+        if let Some(&number) = self.link_numbers.get(&dest[..]) {
+            return number;
+        }
+        let number = self.link_numbers.len() + 1;
+        self.link_numbers.insert(dest.to_string(), number);
+        if let Some(ref mut links) = self.links {
+            // same label as in the text, to match them at a glance
+            let item = add_labelled(links, format!("[{}]", number), TermColor::Blue);
+            let child = item.add_text(dest);
+            child.style.fg = DomColor::from_dark(TermColor::Blue);
+            child.style.underline = true;
+            child.style.url = true;
+        }
+        number
+    }
+    fn resolve_link(&self, dest: CowStr<'a>) -> CowStr<'a> {
+        match self.opts.link_base {
+            Some(ref base) => CowStr::from(resolve_link(base, &dest)),
+            None => dest,
+        }
+    }
+    fn skip_link(&self, dest: &str) -> bool {
+        (self.opts.skip_anchor_links && dest.starts_with('#'))
+            || (self.opts.skip_mailto_links && dest.starts_with("mailto:"))
+    }
+    fn footnote_number(&mut self, name: &str) -> usize {
+        let next = self.footnote_numbers.len() + 1;
+        *self
+            .footnote_numbers
+            .entry(name.to_string())
+            .or_insert(next)
+    }
+    /// Tell at the end of each footnote which sections reference it
+    fn add_backlinks(&mut self, footnotes: &mut DomBox<'a>) {
+        if !self.opts.footnote_backlinks {
+            return;
+        }
+        for (item, number) in footnotes.children.iter_mut().zip(&self.footnote_defs) {
+            let (Some(sections), Some(content)) =
+                (self.backlinks.get(number), item.children.last_mut())
+            else {
+                continue;
+            };
+            let last = match content.children.last_mut() {
+                Some(
+                    paragraph @ DomBox {
+                        kind: BoxKind::Block,
+                        ..
+                    },
+                ) => paragraph,
+                _ => content,
+            };
+            let child = last.add_text(CowStr::from(format!(" ↩ {}", sections.join(", "))));
+            child.style.fg = DomColor::from_grey(0x80);
+        }
+    }
+    fn next_event(&mut self) -> Option<Event<'a>> {
+        self.replay.pop_front().or_else(|| self.iter.next())
+    }
+    /// Read the whole document, setting aside the footnotes with only inline content
+    fn collect_inline_footnotes(&mut self) {
+        let mut definition: Option<Vec<Event<'a>>> = None;
+        for event in self.iter.by_ref() {
+            match (event, &mut definition) {
+                (event @ Event::Start(Tag::FootnoteDefinition(_)), None) => {
+                    definition = Some(vec![event]);
+                }
+                (Event::End(Tag::FootnoteDefinition(name)), Some(events)) => {
+                    let mut events = std::mem::take(events);
+                    definition = None;
+                    if let Some(inline) = inline_content(&events[1..]) {
+                        self.inline_footnotes.insert(name.to_string(), inline);
+                    } else {
+                        events.push(Event::End(Tag::FootnoteDefinition(name)));
+                        self.replay.extend(events);
+                    }
+                }
+                (event, Some(events)) => events.push(event),
+                (event, None) => self.replay.push_back(event),
+            }
+        }
+    }
+    /// Read the whole document to know all the headings, so that links can point
+    /// to those coming after them
+    fn collect_anchors(&mut self) {
+        self.replay.extend(self.iter.by_ref());
+        let mut slugs = HashMap::new();
+        let mut counters = [0; 6];
+        let top = self.replay.iter().filter_map(|event| match event {
+            Event::Start(Tag::Heading(level, ..)) => Some(*level as usize),
+            _ => None,
+        });
+        let top = top.min().unwrap_or(1);
+        let mut level = top;
+        let mut heading: Option<(Option<&str>, String)> = None;
+        for event in &self.replay {
+            match (event, &mut heading) {
+                (Event::Start(Tag::Heading(heading_level, id, _)), _) => {
+                    level = *heading_level as usize;
+                    counters[level - 1] += 1;
+                    counters[level..].fill(0);
+                    heading = Some((*id, String::new()));
+                }
+                (Event::Text(text) | Event::Code(text), Some((_, title))) => title.push_str(text),
+                (Event::End(Tag::Heading(..)), Some((id, title))) => {
+                    let anchor = match id {
+                        Some(id) => id.to_string(),
+                        None => slug(title, &mut slugs),
+                    };
+                    let number = match self.opts.number_headings {
+                        true => section_number(&counters[top - 1..level]),
+                        false => (self.heading_anchors.len() + 1).to_string(),
+                    };
+                    let title = std::mem::take(title);
+                    self.anchors.insert(anchor.clone(), (number.clone(), title));
+                    self.heading_anchors.push_back((anchor, number));
+                    heading = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    /// Keep only the section with the title closest to `name`, and the footnotes
+    fn select_section(&mut self, name: &str) {
+        let name = normalize(name);
+        let titles: Vec<_> = self
+            .heading_anchors
+            .iter()
+            .map(|(anchor, _)| normalize(&self.anchors[anchor].1))
+            .collect();
+        let wanted = titles
+            .iter()
+            .position(|title| *title == name)
+            .or_else(|| titles.iter().position(|title| title.contains(&name)));
+        if wanted.is_none() {
+            self.replay.clear();
+            return;
+        }
+        let mut anchors = std::mem::take(&mut self.heading_anchors).into_iter();
+        let mut heading = 0;
+        let mut section = None;
+        let mut footnote: Option<Vec<Event<'a>>> = None;
+        let mut footnotes = vec![];
+        let mut references = vec![];
+        for event in std::mem::take(&mut self.replay) {
+            match (&event, &mut footnote) {
+                (Event::Start(Tag::FootnoteDefinition(_)), _) => {
+                    footnote = Some(vec![event]);
+                    continue;
+                }
+                (Event::End(Tag::FootnoteDefinition(_)), Some(events)) => {
+                    events.push(event);
+                    footnotes.extend(footnote.take());
+                    continue;
+                }
+                (_, Some(events)) => {
+                    if let Event::FootnoteReference(ref name) = event {
+                        references.push(name.clone());
+                    }
+                    events.push(event);
+                    continue;
+                }
+                (Event::Start(Tag::Heading(level, ..)), None) => {
+                    let anchor = anchors.next();
+                    section = match section {
+                        Some(top) if *level > top => Some(top),
+                        None if Some(heading) == wanted => Some(*level),
+                        _ => None,
+                    };
+                    heading += 1;
+                    if let (Some(anchor), Some(_)) = (anchor, section) {
+                        self.heading_anchors.push_back(anchor);
+                    }
+                }
+                (Event::FootnoteReference(name), None) if section.is_some() => {
+                    references.push(name.clone());
+                }
+                _ => {}
+            }
+            if section.is_some() {
+                self.replay.push_back(event);
+            }
+        }
+        // footnotes of the section, including those referenced by these footnotes
+        for events in footnotes {
+            match events.first() {
+                Some(Event::Start(Tag::FootnoteDefinition(name))) if references.contains(name) => {
+                    self.replay.extend(events)
+                }
+                _ => {}
+            }
+        }
+    }
+    /// Remove the "[!NOTE]"-like marker starting a GitHub alert blockquote, telling
+    /// its icon, title and color
+    fn take_alert(&mut self) -> Option<(&'static str, &'static str, TermColor)> {
+        let alert = match (
+            self.replay.front(),
+            self.replay.get(1),
+            self.replay.get(2),
+            self.replay.get(3),
+        ) {
+            (
+                Some(Event::Start(Tag::Paragraph)),
+                Some(Event::Text(open)),
+                Some(Event::Text(kind)),
+                Some(Event::Text(close)),
+            ) if **open == *"[" && **close == *"]" => match &kind.to_uppercase()[..] {
+                "!NOTE" => ("ℹ", "Note", TermColor::Blue),
+                "!TIP" => ("★", "Tip", TermColor::Green),
+                "!IMPORTANT" => ("‼", "Important", TermColor::Purple),
+                "!WARNING" => ("⚠", "Warning", TermColor::Yellow),
+                "!CAUTION" => ("✖", "Caution", TermColor::Red),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.replay.drain(1..4);
+        match self.replay.get(1) {
+            Some(Event::SoftBreak) => {
+                self.replay.remove(1);
+            }
+            // the marker was a paragraph on its own
+            Some(Event::End(Tag::Paragraph)) => {
+                self.replay.drain(0..2);
+            }
+            _ => {}
+        }
+        Some(alert)
+    }
+    /// The text up to the `</name>` closing an inline HTML tag - if it isn't
+    /// closed in the same paragraph, the events are left to be read again
+    fn tag_content(&mut self, name: &str) -> Option<String> {
+        let close = format!("</{}>", name);
+        let mut events = vec![];
+        let mut content = String::new();
+        let mut depth = 0;
+        while let Some(event) = self.next_event() {
+            match event {
+                Event::Html(ref html) if html.trim().eq_ignore_ascii_case(&close) => {
+                    return Some(content);
+                }
+                Event::Text(ref text) | Event::Code(ref text) => content.push_str(text),
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => {
+                    events.push(event);
+                    break;
+                }
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+            events.push(event);
+        }
+        for event in events.into_iter().rev() {
+            self.replay.push_front(event);
+        }
+        None
     }
     fn build(&mut self, width: XY) -> DomBox<'a> {
-        self.links = Some(DomBox::new_block());
+        if self.opts.inline_footnotes {
+            self.collect_inline_footnotes();
+        }
+        self.collect_anchors();
+        if let Some(ref name) = self.opts.section {
+            self.select_section(name);
+        }
+        let mut links = DomBox::new_block();
+        links.size.border.left += 2;
+        self.links = Some(links);
         self.footnotes = Some(DomBox::new_block());
         let mut root = DomBox::new_root(width);
+        let (left, right) = self.opts.margins.edges(self.opts.width);
+        root.set_margins(left.into(), right.into());
+        root.style.hyphenate = Some(self.opts.hyphenate);
         self.build_dom(&mut root);
-        if let Some(links) = self.links.take() {
-            root.swallow(links);
+        #[cfg(feature = "search")]
+        if let Some(ref pattern) = self.opts.grep {
+            keep_matching(&mut root, pattern);
+        }
+        if let Some(mut links) = self.links.take() {
+            if !links.children.is_empty() {
+                add_section_title(&mut root, "Links");
+                links.size.margin.bottom = 1.into();
+                root.swallow(links);
+            }
+        }
+        if let Some(mut footnotes) = self.footnotes.take() {
+            if !footnotes.children.is_empty() {
+                self.add_backlinks(&mut footnotes);
+                add_section_title(&mut root, "Footnotes");
+                root.swallow(footnotes);
+            }
         }
-        if let Some(footnotes) = self.footnotes.take() {
-            root.swallow(footnotes);
+        #[cfg(feature = "search")]
+        if let Some(ref pattern) = self.opts.search {
+            let find = |text: &str| {
+                let matches = pattern.find_iter(text).map(|m| m.range());
+                matches.filter(|range| !range.is_empty()).collect()
+            };
+            root.restyle_matches(&find, &self.opts.stylesheet.search_match);
+        }
+        if self.opts.outline_boxes {
+            // the root has its margins instead
+            root.children.iter_mut().for_each(DomBox::outline);
         }
         root
     }
+    #[cfg(feature = "emoji")]
+    fn expand_emoji(&self, text: CowStr<'a>) -> CowStr<'a> {
+        if !self.opts.emoji || self.in_code_block {
+            return text;
+        }
+        match expand_shortcodes(&text) {
+            Some(expanded) => CowStr::from(expanded),
+            None => text,
+        }
+    }
+    #[cfg(not(feature = "emoji"))]
+    fn expand_emoji(&self, text: CowStr<'a>) -> CowStr<'a> {
+        text
+    }
+    /// Adds a text with `$math$` in it
+    fn add_math_text(&mut self, parent: &mut DomBox<'a>, text: CowStr<'a>) {
+        let mut text = text.into_string();
+        // math may have been cut in several texts, e.g. at brackets
+        while let Some(Event::Text(_)) = self.replay.front() {
+            if let Some(Event::Text(next)) = self.replay.pop_front() {
+                text.push_str(&next);
+            }
+        }
+        for (piece, is_math) in math::split(&text) {
+            if is_math {
+                let child = parent.add_text(CowStr::from(math::to_unicode(piece)));
+                child.style.apply(&self.opts.stylesheet.math);
+            } else {
+                parent.add_text(CowStr::from(piece.to_string()));
+            }
+        }
+    }
+    /// Adds a text with `==marked==` parts in it
+    fn add_marked_text(&mut self, parent: &mut DomBox<'a>, text: CowStr<'a>) {
+        let mut text = text.into_string();
+        while let Some(Event::Text(_)) = self.replay.front() {
+            if let Some(Event::Text(next)) = self.replay.pop_front() {
+                text.push_str(&next);
+            }
+        }
+        for (piece, marked) in split_marked(&text) {
+            let child = parent.add_text(CowStr::from(piece.to_string()));
+            if marked {
+                child.style.apply(&self.opts.stylesheet.mark);
+            }
+        }
+    }
+    /// Adds a text ending at most with one newline, which becomes a break
+    fn add_line(&mut self, parent: &mut DomBox<'a>, mut text: CowStr<'a>) {
+        #[cfg(feature = "syntax-highlighting")]
+        if self.detect_lang {
+            self.detect_lang = false;
+            self.highlight_with(self.syntaxes.find_syntax_by_first_line(&text));
+        }
+        #[cfg(feature = "syntax-highlighting")]
+        let palette = &self.opts.palette;
+        #[cfg(feature = "syntax-highlighting")]
+        if let Some(ref mut h) = self.highline {
+            match text {
+                CowStr::Borrowed(text) => {
+                    #[cfg(feature = "rayon")]
+                    if self.ready.is_empty() {
+                        let pending = self.pending.remove(&(text.as_ptr() as usize));
+                        // a thread is on it, else it's quicker to do it here than to wait
+                        if let Some((taken, done)) = pending {
+                            if taken.swap(true, Ordering::AcqRel) {
+                                if let Ok(lines) = done.recv() {
+                                    self.ready = lines.into();
+                                }
+                            }
+                        }
+                    }
+                    #[cfg(feature = "rayon")]
+                    let ranges = self.ready.pop_front().unwrap_or_else(|| h.highlight(text));
+                    #[cfg(not(feature = "rayon"))]
+                    let ranges = h.highlight(text);
+                    for (style, text) in ranges {
+                        add_highlighted(parent, style, CowStr::Borrowed(text), palette);
+                    }
+                }
+                // not borrowed from the markdown, the pieces must be copied
+                text => {
+                    for (style, piece) in h.highlight(&text) {
+                        let piece = CowStr::from(piece.to_string());
+                        add_highlighted(parent, style, piece, palette);
+                    }
+                }
+            }
+            return;
+        }
+        let mut add_break = false;
+        if !text.is_empty() {
+            // check if text ends with a newline
+            let bytes = text.as_bytes();
+            if bytes[bytes.len() - 1] == 10 {
+                add_break = true;
+            }
+        }
+        if add_break {
+            let pos = text.len() - 1;
+            split_at_in_place(&mut text, pos);
+        }
+        parent.add_text(text);
+        if add_break {
+            parent.add_break();
+        }
+    }
     fn build_dom(&mut self, parent: &mut DomBox<'a>) {
-        loop {
-            match self.iter.next() {
-                Some(event) => {
-                    match event {
-                        Event::Start(tag) => {
-                            match tag {
-                                Tag::Paragraph => {
-                                    let child = parent.add_block();
-                                    self.build_dom(child);
-                                    child.size.border.bottom += 1;
+        while let Some(event) = self.next_event() {
+            match event {
+                Event::Start(tag) => {
+                    match tag {
+                        Tag::Paragraph => {
+                            let child = parent.add_block();
+                            child.style.apply(&self.opts.stylesheet.paragraph);
+                            self.build_dom(child);
+                            child.size.margin.bottom = self.opts.spacing.paragraph.into();
+                        }
+                        Tag::Heading(level, _id, _classes) => {
+                            let heading = &self.opts.stylesheet.headings[level as usize - 1];
+                            let (anchor, number) = self.heading_anchors.pop_front().unzip();
+                            let child =
+                                parent.add_header(level as u8, anchor.clone().map(CowStr::from));
+                            child.size.border = heading.border;
+                            // the bottom border is the underline
+                            child.size.margin.bottom = self.opts.spacing.heading.into();
+                            child.style.apply(&heading.style);
+                            if let (Some(number), true) = (number, self.opts.number_headings) {
+                                child.add_text(CowStr::from(format!("{} ", number)));
+                            }
+                            self.build_dom(child);
+                            self.section = Some(child.text());
+                            if let (Some(anchor), true) = (&anchor, self.opts.show_heading_ids) {
+                                let child = child.add_text(CowStr::from(format!(" #{}", anchor)));
+                                child.style.fg = DomColor::from_light(TermColor::Black);
+                            }
+                            if let Some(elements) = self.opts.elements {
+                                elements.on_heading(child, level as u8, anchor.as_deref());
+                            }
+                        }
+                        Tag::Table(aligns) => {
+                            let child = parent.add_table();
+                            child.style.apply(&self.opts.stylesheet.table);
+                            child.size.border = Edges {
+                                top: 1.into(),
+                                bottom: 1.into(),
+                                left: 1.into(),
+                                right: 1.into(),
+                            };
+                            // a table in a cell, from made up events
+                            let aligns = std::mem::replace(&mut self.table_aligns, aligns);
+                            let cell = std::mem::take(&mut self.table_cell);
+                            self.build_dom(child);
+                            self.table_aligns = aligns;
+                            self.table_cell = cell;
+                            child.size.margin.bottom = self.opts.spacing.table.into();
+                        }
+                        Tag::TableHead | Tag::TableRow => self.table_cell.1 = 0,
+                        // the cells of a column go in it, one per row
+                        Tag::TableCell if matches!(parent.kind, BoxKind::Table) => {
+                            let (row, column) = self.table_cell;
+                            self.table_cell.1 += 1;
+                            if column == parent.children.len() {
+                                let child = parent.add_table_column();
+                                for _ in 0..row {
+                                    child.add_table_item();
                                 }
-                                Tag::Heading(level, _id, _classes) => {
-                                    let child = parent.add_header(level as u8);
-                                    child.size.border.bottom += 1;
-                                    match level {
-                                        HeadingLevel::H1 => {
-                                            child.size.border.top += 1;
-                                            child.size.border.left += 1;
-                                            child.size.border.right += 1;
-                                            child.style.border_type = BorderType::Thin;
-                                        }
-                                        HeadingLevel::H2 => {
-                                            child.style.border_type = BorderType::Bold;
-                                        }
-                                        HeadingLevel::H3 => {
-                                            child.style.border_type = BorderType::Double;
-                                        }
-                                        HeadingLevel::H4 => {
-                                            child.style.border_type = BorderType::Thin;
-                                        }
-                                        HeadingLevel::H5 => {
-                                            child.style.border_type = BorderType::Dash;
-                                        }
-                                        HeadingLevel::H6 => {}
-                                    }
-                                    child.style.fg = DomColor::from_dark(TermColor::Purple);
-                                    self.build_dom(child);
+                            }
+                            let child = parent.children[column].add_table_item();
+                            child.size.padding.left = 1.into();
+                            child.size.padding.right = 1.into();
+                            child.style.align = match self.table_aligns.get(column) {
+                                Some(Alignment::Center) => TextAlign::Center,
+                                Some(Alignment::Right) => TextAlign::Right,
+                                _ => TextAlign::Left,
+                            };
+                            if row == 0 {
+                                child.style.apply(&self.opts.stylesheet.table_header);
+                            }
+                            self.build_dom(child);
+                        }
+                        Tag::TableCell => {
+                            let child = parent.add_block();
+                            self.build_dom(child);
+                        }
+                        Tag::BlockQuote => {
+                            if let Some(alert) = self.take_alert() {
+                                let child = parent.add_block();
+                                child.size.border = Edges {
+                                    top: 1.into(),
+                                    bottom: 1.into(),
+                                    left: 1.into(),
+                                    right: 1.into(),
+                                };
+                                child.style.extend = true;
+                                {
+                                    let (icon, title, color) = alert;
+                                    let title =
+                                        child.add_text(CowStr::from(format!("{} {}", icon, title)));
+                                    title.style.fg = DomColor::from_light(color);
+                                    title.style.bold = true;
+                                    child.add_break();
                                 }
-                                Tag::Table(_) => {}
-                                Tag::TableHead => {}
-                                Tag::TableRow => {}
-                                Tag::TableCell => {}
-                                Tag::BlockQuote => {
-                                    let child = parent.add_block();
-                                    self.build_dom(child);
-                                    child.size.border.left += 1;
-                                    child.style.border_type = BorderType::Thin;
-                                    child.style.fg = DomColor::from_dark(TermColor::Cyan);
-                                    let newline = parent.add_block(); // XXX ugly
-                                    newline.add_text(CowStr::from(""));
+                                self.build_dom(child);
+                                // no blank line between the last paragraph and the border
+                                if let Some(last) = child.children.last_mut() {
+                                    last.size.margin.bottom = 0.into();
                                 }
-                                Tag::CodeBlock(info) => {
-                                    {
-                                        let child = parent.add_block();
-                                        child.style.fg = DomColor::from_dark(TermColor::White);
-                                        child.style.bg = DomColor::from_dark(TermColor::Black);
-                                        if let CodeBlockKind::Fenced(syn) = info {
-                                            self.syntax = self.syntaxes.find_syntax_by_token(&syn);
-                                            if let Some(syn) = self.syntax {
-                                                self.highline = Some(HighlightLines::new(
-                                                    syn,
-                                                    &self.themes.themes[self.theme],
-                                                ));
-                                            }
-                                        }
-                                        self.build_dom(child);
+                                child.style.border_type = BorderType::Thin;
+                                child.style.border_fg = DomColor::from_dark(alert.2);
+                                child.size.margin.bottom = self.opts.spacing.quote.into();
+                                if let Some(elements) = self.opts.elements {
+                                    elements.on_blockquote(child);
+                                }
+                                continue;
+                            }
+                            let child = parent.add_block();
+                            {
+                                // keep the content a space away from the border
+                                let content = child.add_block();
+                                content.size.border.left += 1;
+                                content.style.border_type = BorderType::Empty;
+                                self.quote_depth += 1;
+                                self.build_dom(content);
+                                self.quote_depth -= 1;
+                            }
+                            child.size.border.left += 1;
+                            let quotes = &self.opts.stylesheet.quotes;
+                            if !quotes.is_empty() {
+                                child.style.apply(&quotes[self.quote_depth % quotes.len()]);
+                            }
+                            let tint = &self.opts.stylesheet.quote_tint;
+                            if tint.is_set() {
+                                child.style.bg = tint.clone();
+                                child.style.extend = true;
+                            }
+                            if let QuoteStyle::Prefix = self.opts.quote_style {
+                                child.style.border_type = BorderType::Quote;
+                            }
+                            child.size.margin.bottom = self.opts.spacing.quote.into();
+                            if let Some(elements) = self.opts.elements {
+                                elements.on_blockquote(child);
+                            }
+                        }
+                        Tag::CodeBlock(info) => {
+                            self.in_code_block = true;
+                            {
+                                let child = parent.add_block();
+                                child.style.apply(&self.opts.stylesheet.code_block);
+                                child.size.padding = self.opts.stylesheet.code_padding;
+                                child.size.min_width =
+                                    self.opts.stylesheet.code_min_width.map(XY::from);
+                                child.style.hyphenate = Some(false);
+                                if let CodeBlockKind::Fenced(ref syn) = info {
+                                    let lang = fence_lang(syn);
+                                    if !lang.is_empty() {
+                                        let label = child.add_text(CowStr::from(lang.to_string()));
+                                        label.style.apply(&self.opts.stylesheet.code_label);
+                                        child.add_break();
                                     }
-                                    let newline = parent.add_block(); // XXX ugly
-                                    newline.add_text(CowStr::from(""));
                                 }
-                                Tag::List(Some(start)) => {
-                                    let child =
-                                        parent.add_list(Some((start as usize).try_into().unwrap()));
-                                    self.build_dom(child);
-                                    child.size.border.bottom += 1;
+                                self.start_highlighting(&info);
+                                self.build_dom(child);
+                                child.size.margin.bottom = self.opts.spacing.code_block.into();
+                                if let Some(elements) = self.opts.elements {
+                                    let lang = match info {
+                                        CodeBlockKind::Fenced(ref syn) => fence_lang(syn),
+                                        CodeBlockKind::Indented => "",
+                                    };
+                                    elements.on_code_block(child, &lang.to_lowercase());
                                 }
-                                Tag::List(None) => {
-                                    let child = parent.add_list(None);
-                                    self.build_dom(child);
-                                    child.size.border.bottom += 1;
+                            }
+                        }
+                        Tag::List(Some(start)) => {
+                            // markdown numbers have at most 9 digits, made up ones may not
+                            let child = parent.add_list(Some(
+                                XY::try_from(start as usize).unwrap_or(XY::new(u32::MAX)),
+                            ));
+                            self.build_dom(child);
+                            child.size.margin.bottom = self.opts.spacing.list.into();
+                        }
+                        Tag::List(None) => {
+                            let child = parent.add_list(None);
+                            self.bullet_depth += 1;
+                            self.build_dom(child);
+                            self.bullet_depth -= 1;
+                            child.size.margin.bottom = self.opts.spacing.list.into();
+                        }
+                        Tag::Item => {
+                            // a task list item starts with its marker
+                            let task = match self.next_event() {
+                                Some(Event::TaskListMarker(checked)) => Some(checked),
+                                Some(event) => {
+                                    self.replay.push_front(event);
+                                    None
                                 }
-                                Tag::Item => {
-                                    {
-                                        let bullet = parent.add_bullet();
-                                        bullet.style.fg = DomColor::from_light(TermColor::Yellow);
-                                        bullet.size.border.right += 1;
-                                    }
-                                    let child = parent.add_block();
-                                    self.build_dom(child);
+                                None => None,
+                            };
+                            {
+                                let bullet = parent.add_bullet();
+                                bullet.style.fg = DomColor::from_light(TermColor::Yellow);
+                                bullet.size.border.right += 1;
+                                if let Some(checked) = task {
+                                    let (marker, style) = if checked {
+                                        (&self.opts.task_markers.1, &self.opts.stylesheet.task_done)
+                                    } else {
+                                        (&self.opts.task_markers.0, &self.opts.stylesheet.task_todo)
+                                    };
+                                    let child = bullet.add_text(CowStr::from(marker.clone()));
+                                    child.style.apply(style);
                                 }
-                                Tag::Emphasis => {
-                                    let child = parent.add_inline();
-                                    child.style.italic = true;
-                                    self.build_dom(child);
+                            }
+                            let child = parent.add_block();
+                            if let (Some(true), true) = (task, self.opts.strike_done_tasks) {
+                                child.style.apply(&self.opts.stylesheet.done_task);
+                            }
+                            self.build_dom(child);
+                        }
+                        Tag::Emphasis => {
+                            let child = parent.add_inline();
+                            child.style.italic = true;
+                            self.build_dom(child);
+                        }
+                        Tag::Strong => {
+                            let child = parent.add_inline();
+                            child.style.bold = true;
+                            self.build_dom(child);
+                        }
+                        Tag::Strikethrough => {
+                            let child = parent.add_inline();
+                            child.style.strikethrough = true;
+                            self.build_dom(child);
+                        }
+                        Tag::Link(_linktype, dest, _title) => {
+                            let target = dest.strip_prefix('#').and_then(|id| self.anchors.get(id));
+                            if let Some((number, title)) = target {
+                                let reference = format!(" → §{} {}", number, title);
+                                let child = parent.add_inline();
+                                child.style.underline = true;
+                                child.style.fg = DomColor::from_dark(TermColor::Blue);
+                                self.build_dom(child);
+                                if let Some(elements) = self.opts.elements {
+                                    elements.on_link(child, &dest);
                                 }
-                                Tag::Strong => {
-                                    let child = parent.add_inline();
-                                    child.style.bold = true;
-                                    self.build_dom(child);
+                                if self.opts.link_style != LinkStyle::Hidden {
+                                    let child = parent.add_text(CowStr::from(reference));
+                                    child.style.fg = DomColor::from_light(TermColor::Black);
                                 }
-                                Tag::Strikethrough => {
-                                    let child = parent.add_inline();
-                                    child.style.strikethrough = true;
-                                    self.build_dom(child);
+                                continue;
+                            }
+                            let child = parent.add_inline();
+                            child.style.underline = true;
+                            child.style.fg = DomColor::from_dark(TermColor::Blue);
+                            if let LinkStyle::Osc8 = self.opts.link_style {
+                                let link = self.resolve_link(dest.clone());
+                                child.style.link = Some(match link.starts_with('/') {
+                                    true => format!("file://{}", link),
+                                    false => link.to_string(),
+                                });
+                            }
+                            self.build_dom(child);
+                            // autolinks already show their destination
+                            let shown = child.text() == *dest;
+                            if let Some(elements) = self.opts.elements {
+                                elements.on_link(child, &dest);
+                            }
+                            let dest = self.resolve_link(dest);
+                            match self.opts.link_style {
+                                LinkStyle::Inline if !shown => {
+                                    let child =
+                                        parent.add_text(CowStr::from(format!(" ({})", dest)));
+                                    child.style.fg = DomColor::from_light(TermColor::Black);
+                                    child.style.url = true;
                                 }
-                                Tag::Link(_linktype, dest, _title) => {
-                                    if let Some(mut links) = self.links.take() {
-                                        {
-                                            let child = links.add_text(dest);
-                                            child.style.fg = DomColor::from_dark(TermColor::Blue);
-                                            child.style.underline = true;
-                                        }
-                                        {
-                                            links.add_break();
-                                        }
-                                        self.links = Some(links);
-                                    }
-                                    let child = parent.add_inline();
-                                    child.style.underline = true;
+                                LinkStyle::Reference if !shown && !self.skip_link(&dest) => {
+                                    let number = self.link_number(dest);
+                                    let child =
+                                        parent.add_text(CowStr::from(format!("[{}]", number)));
                                     child.style.fg = DomColor::from_dark(TermColor::Blue);
-                                    self.build_dom(child);
                                 }
-                                Tag::Image(_linktype, dest, title) => {
-                                    {
-                                        let child = parent.add_text(title);
-                                        child.style.fg = DomColor::from_light(TermColor::Black);
-                                        child.style.bg = DomColor::from_dark(TermColor::Yellow);
-                                    }
-                                    {
-                                        let child = parent.add_text(dest);
-                                        child.style.fg = DomColor::from_dark(TermColor::Blue);
-                                        child.style.bg = DomColor::from_dark(TermColor::Yellow);
-                                        child.style.underline = true;
-                                    }
-                                    let child = parent.add_inline();
-                                    child.style.italic = true;
+                                _ => {}
+                            }
+                        }
+                        Tag::Image(_linktype, dest, title) => {
+                            let dest = self.resolve_link(dest);
+                            let image = parent.add_inline();
+                            {
+                                let child = image.add_text(title.clone());
+                                child.style.fg = DomColor::from_light(TermColor::Black);
+                                child.style.bg = DomColor::from_dark(TermColor::Yellow);
+                            }
+                            {
+                                let child = image.add_text(dest.clone());
+                                child.style.fg = DomColor::from_dark(TermColor::Blue);
+                                child.style.bg = DomColor::from_dark(TermColor::Yellow);
+                                child.style.underline = true;
+                                child.style.url = true;
+                            }
+                            {
+                                let child = image.add_inline();
+                                child.style.italic = true;
+                                self.build_dom(child);
+                            }
+                            if let Some(elements) = self.opts.elements {
+                                elements.on_image(image, &dest, &title);
+                            }
+                        }
+                        Tag::FootnoteDefinition(name) => {
+                            let number = self.footnote_number(&name);
+                            self.footnote_defs.push(number);
+                            if let Some(mut footnotes) = self.footnotes.take() {
+                                {
+                                    let child = add_labelled(
+                                        &mut footnotes,
+                                        format!("{}.", number),
+                                        TermColor::Green,
+                                    );
                                     self.build_dom(child);
                                 }
-                                Tag::FootnoteDefinition(name) => {
-                                    if let Some(mut footnotes) = self.footnotes.take() {
-                                        {
-                                            let child = footnotes.add_text(name);
-                                            child.style.fg = DomColor::from_dark(TermColor::Green);
-                                            child.style.underline = true;
-                                        }
-                                        self.build_dom(&mut footnotes);
-                                        self.footnotes = Some(footnotes);
-                                    }
-                                }
+                                self.footnotes = Some(footnotes);
                             }
                         }
-                        Event::End(tag) => {
-                            match tag {
-                                Tag::Paragraph => {
-                                    break;
-                                }
-                                Tag::Heading(..) => {
-                                    break;
-                                }
-                                Tag::Table(_) => {}
-                                Tag::TableHead => {}
-                                Tag::TableRow => {}
-                                Tag::TableCell => {}
-                                Tag::BlockQuote => {
-                                    break;
-                                }
-                                Tag::CodeBlock(_) => {
-                                    self.highline = None;
-                                    self.syntax = None;
-                                    break;
-                                }
-                                Tag::List(None) => {
-                                    for child in &mut parent.children {
-                                        {
-                                            if let BoxKind::ListBullet = child.kind {
-                                                child.add_text(CowStr::from("*"));
-                                            }
-                                        }
-                                    }
-                                    break;
+                    }
+                }
+                Event::End(tag) => {
+                    match tag {
+                        Tag::Paragraph => {
+                            break;
+                        }
+                        Tag::Heading(..) => {
+                            break;
+                        }
+                        Tag::Table(_) => {
+                            break;
+                        }
+                        // the rows with fewer cells get empty ones
+                        Tag::TableHead | Tag::TableRow => {
+                            let row = self.table_cell.0;
+                            self.table_cell.0 += 1;
+                            for column in &mut parent.children {
+                                while column.children.len() <= row {
+                                    column.add_table_item();
                                 }
-                                Tag::List(Some(start)) => {
-                                    let mut i = start;
-                                    // TODO resize all bullets like the last one
-                                    //let end = start + node.children.len() / 2;
-                                    for child in &mut parent.children {
-                                        {
-                                            if let BoxKind::ListBullet = child.kind {
-                                                child.add_text(CowStr::from(i.to_string()));
-                                                i += 1;
-                                            }
-                                        }
+                            }
+                        }
+                        Tag::TableCell => {
+                            break;
+                        }
+                        Tag::BlockQuote => {
+                            break;
+                        }
+                        Tag::CodeBlock(_) => {
+                            self.stop_highlighting();
+                            self.in_code_block = false;
+                            break;
+                        }
+                        Tag::List(None) => {
+                            let bullets = &self.opts.bullets;
+                            let bullet = match bullets.len() {
+                                0 => "*",
+                                n => &bullets[self.bullet_depth.saturating_sub(1) % n],
+                            };
+                            for child in &mut parent.children {
+                                {
+                                    // task list items already have their marker
+                                    if let (BoxKind::ListBullet, true) =
+                                        (&child.kind, child.children.is_empty())
+                                    {
+                                        child.add_text(CowStr::from(bullet.to_string()));
                                     }
-                                    break;
-                                }
-                                Tag::Item => {
-                                    break;
-                                }
-                                Tag::Emphasis => {
-                                    break;
-                                }
-                                Tag::Strong => {
-                                    break;
-                                }
-                                Tag::Strikethrough => {
-                                    break;
-                                }
-                                Tag::Link(..) => {
-                                    break;
-                                }
-                                Tag::Image(..) => {
-                                    break;
-                                }
-                                Tag::FootnoteDefinition(..) => {
-                                    break;
                                 }
                             }
+                            break;
                         }
-                        // FIXME handle Code specially
-                        Event::Text(mut text) | Event::Code(mut text) => {
-                            if let Some(ref mut h) = self.highline {
-                                match text {
-                                    CowStr::Borrowed(text) => {
-                                        let ranges = h.highlight(&text);
-                                        for (style, mut text) in ranges {
-                                            let mut add_break = false;
-                                            if text.len() > 0 {
-                                                // check if text ends with a newline
-                                                let bytes = text.as_bytes();
-                                                if bytes[bytes.len() - 1] == 10 {
-                                                    add_break = true;
-                                                }
-                                            }
-                                            if add_break {
-                                                text = &text[..text.len() - 1];
-                                            }
-                                            {
-                                                let child = parent.add_text(CowStr::Borrowed(text));
-                                                child.style.fg = DomColor::from_color_lo(
-                                                    style.foreground.r,
-                                                    style.foreground.g,
-                                                    style.foreground.b,
-                                                );
-                                                child.style.bold |= style
-                                                    .font_style
-                                                    .intersects(highlighting::FontStyle::BOLD);
-                                                child.style.italic |= style
-                                                    .font_style
-                                                    .intersects(highlighting::FontStyle::ITALIC);
-                                                child.style.underline |= style
-                                                    .font_style
-                                                    .intersects(highlighting::FontStyle::UNDERLINE);
-                                            }
-                                            if add_break {
-                                                parent.add_break();
-                                            }
+                        Tag::List(Some(start)) => {
+                            let mut i = start;
+                            let count = parent
+                                .children
+                                .iter()
+                                .filter(|child| matches!(child.kind, BoxKind::ListBullet))
+                                .count() as u64;
+                            // right-align the numbers on the widest one
+                            let last = start.saturating_add(count.saturating_sub(1));
+                            let width = last.to_string().len();
+                            for child in &mut parent.children {
+                                {
+                                    if let BoxKind::ListBullet = child.kind {
+                                        if child.children.is_empty() {
+                                            let number = format!("{:>1$}.", i, width);
+                                            child.add_text(CowStr::from(number));
                                         }
+                                        i = i.saturating_add(1);
                                     }
-                                    _ => unimplemented!(),
-                                }
-                            } else {
-                                let mut add_break = false;
-                                if text.len() > 0 {
-                                    // check if text ends with a newline
-                                    let bytes = text.as_bytes();
-                                    if bytes[bytes.len() - 1] == 10 {
-                                        add_break = true;
-                                    }
-                                }
-                                if add_break {
-                                    let pos = text.len() - 1;
-                                    split_at_in_place(&mut text, pos);
-                                }
-                                parent.add_text(text);
-                                if add_break {
-                                    parent.add_break();
                                 }
                             }
+                            break;
                         }
-                        Event::TaskListMarker(checked) => {
-                            let child =
-                                parent.add_text(CowStr::from(if checked { "[ ]" } else { "[X]" }));
-                            self.build_dom(child);
+                        Tag::Item => {
+                            break;
                         }
-                        Event::Rule => {
-                            let child = parent.add_block();
-                            child.style.extend = true;
-                            child.size.border.bottom += 1;
-                            child.style.border_type = BorderType::Thin;
-                            child.style.fg = DomColor::from_dark(TermColor::Yellow);
+                        Tag::Emphasis => {
+                            break;
                         }
-                        Event::Html(html) => {
-                            let child = parent.add_text(html);
-                            child.style.fg = DomColor::from_light(TermColor::Red);
+                        Tag::Strong => {
+                            break;
                         }
-                        Event::SoftBreak => {
-                            parent.add_break();
+                        Tag::Strikethrough => {
+                            break;
                         }
-                        Event::HardBreak => {
-                            parent.add_break();
+                        Tag::Link(..) => {
+                            break;
                         }
-                        Event::FootnoteReference(name) => {
-                            let child = parent.add_text(name);
-                            child.style.fg = DomColor::from_dark(TermColor::Green);
-                            child.style.underline = true;
+                        Tag::Image(..) => {
+                            break;
                         }
+                        Tag::FootnoteDefinition(..) => {
+                            break;
+                        }
+                    }
+                }
+                Event::Code(text) => {
+                    let child = parent.add_text(text);
+                    child.style.apply(&self.opts.stylesheet.inline_code);
+                    child.style.hyphenate = Some(false);
+                }
+                Event::Text(text) => {
+                    let mut text = self.expand_emoji(text);
+                    if self.opts.math && !self.in_code_block && text.contains('$') {
+                        self.add_math_text(parent, text);
+                        continue;
+                    }
+                    if self.opts.highlight && !self.in_code_block && text.contains("==") {
+                        self.add_marked_text(parent, text);
+                        continue;
+                    }
+                    // a code block comes as a single text, cut it in lines
+                    while let Some(pos) = text.find('\n') {
+                        let rest = split_at_in_place(&mut text, pos + 1);
+                        self.add_line(parent, text);
+                        text = rest;
+                    }
+                    if !text.is_empty() {
+                        self.add_line(parent, text);
+                    }
+                }
+                // taken by the list item it starts
+                Event::TaskListMarker(_) => {}
+                Event::Rule => {
+                    let child = parent.add_block();
+                    child.style.apply(&self.opts.stylesheet.rule);
+                    let width = match &self.opts.rule_style {
+                        RuleStyle::Ornament(ornament) => {
+                            child.style.border_type = BorderType::Empty;
+                            child.style.align = TextAlign::Center;
+                            child.add_text(CowStr::from(ornament.clone()));
+                            continue;
+                        }
+                        RuleStyle::Full => 100,
+                        RuleStyle::Centered(width) => {
+                            child.style.align = TextAlign::Center;
+                            *width
+                        }
+                        RuleStyle::Partial(width) => *width,
+                    };
+                    child.style.extend = true;
+                    child.style.width = Some(width);
+                    child.size.border.bottom += 1;
+                }
+                Event::Html(html) if is_tag(&html, "sup") || is_tag(&html, "sub") => {
+                    let sup = is_tag(&html, "sup");
+                    let Some(content) = self.tag_content(if sup { "sup" } else { "sub" }) else {
+                        let child = parent.add_text(html);
+                        child.style.fg = DomColor::from_light(TermColor::Red);
+                        continue;
+                    };
+                    let (script, marker) = if sup {
+                        (math::superscript(&content), '^')
+                    } else {
+                        (math::subscript(&content), '_')
+                    };
+                    let script =
+                        script.unwrap_or_else(|| format!("{}{}", marker, math::operand(content)));
+                    parent.add_text(CowStr::from(script));
+                }
+                Event::Html(html) if is_tag(&html, "kbd") => match self.tag_content("kbd") {
+                    Some(key) => {
+                        let child = parent.add_text(CowStr::from(format!(" {} ", key.trim())));
+                        child.style.apply(&self.opts.stylesheet.kbd);
+                    }
+                    None => {
+                        let child = parent.add_text(html);
+                        child.style.fg = DomColor::from_light(TermColor::Red);
                     }
+                },
+                Event::Html(html) => {
+                    let child = parent.add_text(html);
+                    child.style.fg = DomColor::from_light(TermColor::Red);
+                }
+                // the text is reflowed, only hard breaks are kept
+                Event::SoftBreak => {
+                    parent.add_text(CowStr::Borrowed(" "));
+                }
+                Event::HardBreak => {
+                    parent.add_break();
+                }
+                Event::FootnoteReference(name) => {
+                    let content = self.inline_footnotes.get(&name[..]);
+                    // a footnote referencing itself gets its number, inside itself
+                    if let (Some(content), false) = (content, self.expanding.contains(&name)) {
+                        self.expanding.push(name.clone());
+                        let end = Event::End(Tag::FootnoteDefinition(name));
+                        let events = [Event::Text(CowStr::from(" ["))]
+                            .into_iter()
+                            .chain(content.iter().cloned())
+                            .chain([Event::Text(CowStr::from("]")), end]);
+                        // handle them right now, before what follows the reference
+                        for event in events.rev() {
+                            self.replay.push_front(event);
+                        }
+                        let child = parent.add_inline();
+                        child.style.fg = DomColor::from_light(TermColor::Black);
+                        self.build_dom(child);
+                        self.expanding.pop();
+                        continue;
+                    }
+                    let number = self.footnote_number(&name);
+                    if let Some(ref section) = self.section {
+                        let sections = self.backlinks.entry(number).or_default();
+                        if !sections.contains(section) {
+                            sections.push(section.clone());
+                        }
+                    }
+                    let number =
+                        math::superscript(&number.to_string()).expect("digits have superscripts");
+                    let child = parent.add_text(CowStr::from(number));
+                    child.style.fg = DomColor::from_dark(TermColor::Green);
+                }
+            }
+        }
+    }
+}
+
+/// The pieces of `text`, telling which ones are `==marked==` (without their
+/// equal signs) - the marks must hug the text, like emphasis
+fn split_marked(text: &str) -> Vec<(&str, bool)> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("==") {
+        let inner = &rest[start + 2..];
+        match inner.find("==") {
+            Some(end)
+                if end > 0
+                    && !inner.starts_with(char::is_whitespace)
+                    && !inner[..end].ends_with(char::is_whitespace) =>
+            {
+                if start > 0 {
+                    pieces.push((&rest[..start], false));
+                }
+                pieces.push((&inner[..end], true));
+                rest = &inner[end + 2..];
+            }
+            _ => {
+                pieces.push((&rest[..start + 2], false));
+                rest = inner;
+            }
+        }
+    }
+    if !rest.is_empty() {
+        pieces.push((rest, false));
+    }
+    pieces
+}
+
+/// Whether `html` is the `<name>` opening tag, whatever its case
+pub fn is_tag(html: &str, name: &str) -> bool {
+    let tag = html.trim();
+    tag.len() == name.len() + 2
+        && tag.starts_with('<')
+        && tag.ends_with('>')
+        && tag[1..tag.len() - 1].eq_ignore_ascii_case(name)
+}
+
+/// Like "1.2.3", from the counters of the levels down to the current one
+fn section_number(counters: &[usize]) -> String {
+    let numbers: Vec<_> = counters.iter().map(usize::to_string).collect();
+    numbers.join(".")
+}
+
+/// Lowercase letters and digits only, to compare titles loosely
+fn normalize(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The GitHub-like anchor of a heading: lowercase, without punctuation and with
+/// dashes between words - `slugs` counts them to make them unique
+fn slug(title: &str, slugs: &mut HashMap<String, usize>) -> String {
+    let slug: String = title
+        .to_lowercase()
+        .chars()
+        .filter(|&c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect();
+    let count = slugs.entry(slug.clone()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => slug,
+        n => format!("{}-{}", slug, n - 1),
+    }
+}
+
+/// `dest` as seen from `base`, a directory path or URL, unless it's absolute already
+fn resolve_link(base: &str, dest: &str) -> String {
+    if dest.starts_with('#') || has_scheme(dest) {
+        return dest.to_string();
+    }
+    // keep the scheme and host of URLs out of the path
+    let (root, path) = match base.find("://") {
+        Some(pos) => {
+            let host_end = base[pos + 3..]
+                .find('/')
+                .map_or(base.len(), |end| pos + 3 + end);
+            base.split_at(host_end)
+        }
+        None => ("", base),
+    };
+    let joined = if dest.starts_with('/') {
+        dest.to_string()
+    } else {
+        format!("{}/{}", path.trim_end_matches('/'), dest)
+    };
+    let mut segments = vec![];
+    for segment in joined.split('/') {
+        match (segment, segments.last()) {
+            (".", _) => {}
+            ("..", None | Some(&"..")) => segments.push(segment),
+            // can't go above the root
+            ("..", Some(&"")) => {}
+            ("..", Some(_)) => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    format!("{}{}", root, segments.join("/"))
+}
+
+/// Like "https:" or "mailto:"
+fn has_scheme(dest: &str) -> bool {
+    match dest.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// Keep only the top-level blocks with a match of `pattern`, each after the
+/// heading of its section if it doesn't match itself
+#[cfg(feature = "search")]
+fn keep_matching(root: &mut DomBox, pattern: &regex::Regex) {
+    let mut heading = None;
+    for child in std::mem::take(&mut root.children) {
+        let is_heading = matches!(child.kind, BoxKind::Header(..));
+        if !pattern.is_match(&child.text()) {
+            if is_heading {
+                heading = Some(child);
+            }
+            continue;
+        }
+        if is_heading {
+            heading = None;
+        }
+        root.children.extend(heading.take());
+        root.children.push(child);
+    }
+}
+
+/// Add a title ruled like an horizontal rule, for the sections appended to the document
+fn add_section_title(root: &mut DomBox, title: &'static str) {
+    let child = root.add_block();
+    child.style.extend = true;
+    child.size.border.bottom += 1;
+    child.style.border_type = BorderType::Thin;
+    child.style.fg = DomColor::from_dark(TermColor::Yellow);
+    child.add_text(CowStr::from(title));
+}
+
+/// `text` with its `:shortcode:`s replaced by their emoji, if it has any
+#[cfg(feature = "emoji")]
+fn expand_shortcodes(text: &str) -> Option<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    let mut found = false;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        let emoji = after.find(':').and_then(|end| {
+            let name = &after[..end];
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c));
+            valid.then(|| emojis::get_by_shortcode(name).map(|emoji| (emoji, end)))?
+        });
+        match emoji {
+            Some((emoji, end)) => {
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(emoji.as_str());
+                rest = &after[end + 1..];
+                found = true;
+            }
+            None => {
+                expanded.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    found.then_some(expanded)
+}
+
+/// Add a list item with the given label, returning the box for its content
+fn add_labelled<'a, 'p>(
+    parent: &'p mut DomBox<'a>,
+    label: String,
+    color: TermColor,
+) -> &'p mut DomBox<'a> {
+    let item = parent.add_list(None);
+    {
+        let bullet = item.add_bullet();
+        let child = bullet.add_text(CowStr::from(label));
+        child.style.fg = DomColor::from_dark(color);
+        bullet.size.border.right += 1;
+    }
+    item.add_block()
+}
+
+/// The events of a footnote, without its paragraphs, if it only has inline content
+fn inline_content<'a>(events: &[Event<'a>]) -> Option<Vec<Event<'a>>> {
+    let mut inline = vec![];
+    for event in events {
+        match event {
+            Event::Start(Tag::Paragraph) if !inline.is_empty() => {
+                inline.push(Event::Text(CowStr::from(" ")));
+            }
+            Event::Start(Tag::Paragraph) | Event::End(Tag::Paragraph) => {}
+            Event::Start(tag) | Event::End(tag) => match tag {
+                Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) => {
+                    inline.push(event.clone())
                 }
-                None => break,
+                _ => return None,
+            },
+            Event::Text(_) | Event::Code(_) | Event::SoftBreak | Event::FootnoteReference(_) => {
+                inline.push(event.clone())
             }
+            _ => return None,
         }
     }
+    Some(inline)
+}
+
+/// Add a piece of a highlighted code line, its newline becoming a break
+#[cfg(feature = "syntax-highlighting")]
+fn add_highlighted<'a>(
+    parent: &mut DomBox<'a>,
+    style: highlighting::Style,
+    mut text: CowStr<'a>,
+    palette: &Palette,
+) {
+    let add_break = text.ends_with('\n');
+    if add_break {
+        let pos = text.len() - 1;
+        split_at_in_place(&mut text, pos);
+    }
+    let child = parent.add_text(text);
+    let color = style.foreground;
+    child.style.fg = DomColor::from_rgb(color.r, color.g, color.b, palette);
+    child.style.bold |= style.font_style.intersects(highlighting::FontStyle::BOLD);
+    child.style.italic |= style.font_style.intersects(highlighting::FontStyle::ITALIC);
+    child.style.underline |= style
+        .font_style
+        .intersects(highlighting::FontStyle::UNDERLINE);
+    if add_break {
+        parent.add_break();
+    }
+}
+
+/// The language of a code fence info string, without attributes
+/// like in "rust,ignore" or "python title=x.py"
+pub fn fence_lang(info: &str) -> &str {
+    info.split([',', ' ']).next().unwrap_or_default()
+}
+
+/// Whether the code blocks fenced with this language get highlighted
+#[cfg(feature = "syntax-highlighting")]
+pub fn knows_lang(lang: &str, opts: &RenderOptions) -> bool {
+    let lang = lang.to_lowercase();
+    let lang = opts.lang_aliases.get(&lang).unwrap_or(&lang);
+    match opts.syntaxes {
+        Some(syntaxes) => syntaxes.find_syntax_by_token(lang).is_some(),
+        None => SYNTAXES.with(|syntaxes| syntaxes.find_syntax_by_token(lang).is_some()),
+    }
 }
 
-pub fn push_ansi<'a, I: Iterator<Item = Event<'a>>>(iter: I, width: XY) {
-    let syntaxes = SyntaxSet::load_defaults_newlines();
-    let themes = highlighting::ThemeSet::load_defaults();
-    let mut ctx = Ctx::new(iter, &syntaxes, &themes);
-    let mut root = ctx.build(width);
-    //println!("root:\n{:#?}\n", root);
-    root.layout();
-    //println!("root:\n{:#?}\n", root);
-    root.render();
+/// How deep tags can be nested: the DOM is built, laid out and rendered
+/// recursively, the tags deeper than that are left out, keeping their content
+const MAX_DEPTH: usize = 64;
+
+/// The events with their tags properly nested, whatever was made up or filtered
+/// out: an end without its start is dropped, and the tags left open are closed
+/// when an enclosing one ends, or at the end of the stream
+fn balanced<'a, I: Iterator<Item = Event<'a>>>(iter: I) -> impl Iterator<Item = Event<'a>> {
+    // with whether they were kept, not being too deep
+    let mut open: Vec<(Tag<'a>, bool)> = vec![];
+    let mut iter = iter.fuse();
+    let mut pending = VecDeque::new();
+    std::iter::from_fn(move || loop {
+        if let Some(event) = pending.pop_front() {
+            return Some(event);
+        }
+        match iter.next() {
+            Some(Event::Start(tag)) => {
+                let kept = open.iter().filter(|(_, kept)| *kept).count() < MAX_DEPTH;
+                open.push((tag.clone(), kept));
+                if kept {
+                    return Some(Event::Start(tag));
+                }
+                // the blocks left out still start on their own line
+                let inline = matches!(
+                    tag,
+                    Tag::Emphasis
+                        | Tag::Strong
+                        | Tag::Strikethrough
+                        | Tag::Link(..)
+                        | Tag::Image(..)
+                );
+                if !inline {
+                    return Some(Event::HardBreak);
+                }
+            }
+            Some(Event::End(tag)) => {
+                let kind = std::mem::discriminant(&tag);
+                let Some(depth) = open
+                    .iter()
+                    .rposition(|(t, _)| std::mem::discriminant(t) == kind)
+                else {
+                    continue;
+                };
+                let closed = open.drain(depth..).rev();
+                pending.extend(
+                    closed
+                        .filter(|(_, kept)| *kept)
+                        .map(|(tag, _)| Event::End(tag)),
+                );
+            }
+            Some(event) => return Some(event),
+            None => match open.pop() {
+                Some((tag, true)) => return Some(Event::End(tag)),
+                Some((_, false)) => {}
+                None => return None,
+            },
+        }
+    })
+}
+
+/// Build the (not yet laid out) DOM for a stream of markdown events
+pub fn build_dom<'a, I: Iterator<Item = Event<'a>>>(iter: I, opts: &RenderOptions) -> DomBox<'a> {
+    // the parsing happens while building
+    let _span = trace::span!("dom-build");
+    let iter = trace::timed!("parse", iter);
+    let iter = iter.filter_map(|event| {
+        let mut filters = opts.filters.iter();
+        filters.try_fold(event, |event, filter| filter(event))
+    });
+    let iter = balanced(iter);
+    #[cfg(feature = "syntax-highlighting")]
+    {
+        let events: Vec<Event<'a>> = iter.collect();
+        // loading syntect's sets is slow, don't for documents without code to highlight
+        if !has_code(&events, opts) {
+            let syntaxes = SyntaxSet::new();
+            let themes = highlighting::ThemeSet {
+                themes: Default::default(),
+            };
+            return Ctx::new(events.into_iter(), opts, &syntaxes, &themes).build(opts.width.into());
+        }
+        // custom sets can't be used by other threads
+        #[cfg(feature = "rayon")]
+        if opts.syntaxes.is_none() && opts.themes.is_none() {
+            return build_dom_parallel(events, opts);
+        }
+        with_highlighting_sets(opts, |syntaxes, themes| {
+            Ctx::new(events.into_iter(), opts, syntaxes, themes).build(opts.width.into())
+        })
+    }
+    #[cfg(not(feature = "syntax-highlighting"))]
+    Ctx::new(iter, opts).build(opts.width.into())
+}
+
+/// There are code blocks which may be highlighted: fenced ones, or indented ones
+/// if their language is to be guessed
+#[cfg(feature = "syntax-highlighting")]
+fn has_code(events: &[Event], opts: &RenderOptions) -> bool {
+    opts.code_colors
+        && events.iter().any(|event| match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => true,
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)) => opts.detect_lang,
+            _ => false,
+        })
+}
+
+/// Build the DOM like `build_dom`, with the fenced code blocks highlighted
+/// meanwhile by the threads of rayon's pool
+#[cfg(feature = "rayon")]
+fn build_dom_parallel<'a>(events: Vec<Event<'a>>, opts: &RenderOptions) -> DomBox<'a> {
+    let mut pending = HashMap::new();
+    let mut jobs = vec![];
+    for (lang, lines) in fenced_blocks(&events) {
+        let lang = opts.lang_aliases.get(&lang).cloned().unwrap_or(lang);
+        let (done, result) = mpsc::channel();
+        let taken = Arc::new(AtomicBool::new(false));
+        pending.insert(lines[0].as_ptr() as usize, (taken.clone(), result));
+        jobs.push((lang, opts.theme, lines, taken, done));
+    }
+    if jobs.is_empty() || rayon::current_num_threads() < 2 {
+        // not worth loading the syntaxes on other threads
+        return SYNTAXES.with(|syntaxes| {
+            THEMES.with(|themes| {
+                Ctx::new(events.into_iter(), opts, syntaxes, themes).build(opts.width.into())
+            })
+        });
+    }
+    rayon::in_place_scope(|scope| {
+        for job in jobs {
+            scope.spawn(move |_| highlight_block(job));
+        }
+        SYNTAXES.with(|syntaxes| {
+            THEMES.with(|themes| {
+                let mut ctx = Ctx::new(events.into_iter(), opts, syntaxes, themes);
+                ctx.pending = pending;
+                ctx.build(opts.width.into())
+            })
+        })
+    })
+}
+
+/// Highlight a queued code block, unless the DOM builder got to it first
+#[cfg(feature = "rayon")]
+fn highlight_block((lang, theme, lines, taken, done): HighlightJob) {
+    if taken.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    SYNTAXES.with(|syntaxes| {
+        THEMES.with(|themes| {
+            // without a syntax nothing is sent, the block won't be highlighted
+            let syntax = syntaxes.find_syntax_by_token(&lang);
+            if let (Some(syntax), Some(theme)) = (syntax, code_theme(themes, theme)) {
+                let mut highline = HighlightLines::new(syntax, theme);
+                let lines = lines.iter().map(|line| highline.highlight(line)).collect();
+                // the document may be built already, e.g. if it panicked
+                let _ = done.send(lines);
+            }
+        })
+    })
+}
+
+/// The language and lines of the fenced code blocks, as they'll be added - blocks
+/// with texts not borrowed from the markdown are left out
+#[cfg(feature = "rayon")]
+fn fenced_blocks<'a>(events: &[Event<'a>]) -> Vec<(String, Vec<&'a str>)> {
+    let mut blocks = vec![];
+    let mut block = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                block = Some((fence_lang(info).to_lowercase(), vec![]));
+            }
+            Event::Text(text) => match (&mut block, text) {
+                (Some((_, lines)), CowStr::Borrowed(text)) => {
+                    lines.extend(text.split_inclusive('\n'))
+                }
+                (Some(_), _) => block = None,
+                (None, _) => {}
+            },
+            Event::End(Tag::CodeBlock(_)) => {
+                blocks.extend(block.take().filter(|(_, lines)| !lines.is_empty()));
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// The theme named `name`, or any theme if a custom set doesn't have it
+#[cfg(feature = "syntax-highlighting")]
+fn code_theme<'t>(
+    themes: &'t highlighting::ThemeSet,
+    name: &str,
+) -> Option<&'t highlighting::Theme> {
+    themes
+        .themes
+        .get(name)
+        .or_else(|| themes.themes.values().next())
+}
+
+/// Call `f` with the syntaxes and themes from `opts`, or the default ones
+#[cfg(feature = "syntax-highlighting")]
+fn with_highlighting_sets<R, F>(opts: &RenderOptions, f: F) -> R
+where
+    F: FnOnce(&SyntaxSet, &highlighting::ThemeSet) -> R,
+{
+    match (opts.syntaxes, opts.themes) {
+        (Some(syntaxes), Some(themes)) => f(syntaxes, themes),
+        (Some(syntaxes), None) => THEMES.with(|themes| f(syntaxes, themes)),
+        (None, Some(themes)) => SYNTAXES.with(|syntaxes| f(syntaxes, themes)),
+        (None, None) => SYNTAXES.with(|syntaxes| THEMES.with(|themes| f(syntaxes, themes))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ends_without_start_dropped() {
+        let events = vec![Event::End(Tag::Item), Event::Text(CowStr::Borrowed("a"))];
+        let balanced: Vec<_> = balanced(events.into_iter()).collect();
+        assert_eq!(balanced, [Event::Text(CowStr::Borrowed("a"))]);
+    }
+
+    #[test]
+    fn open_tags_closed() {
+        let events = vec![
+            Event::Start(Tag::List(None)),
+            Event::Start(Tag::Item),
+            Event::Start(Tag::Emphasis),
+            Event::End(Tag::List(None)),
+            Event::Start(Tag::Paragraph),
+        ];
+        let balanced: Vec<_> = balanced(events.into_iter()).collect();
+        assert_eq!(
+            balanced,
+            [
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Start(Tag::Emphasis),
+                Event::End(Tag::Emphasis),
+                Event::End(Tag::Item),
+                Event::End(Tag::List(None)),
+                Event::Start(Tag::Paragraph),
+                Event::End(Tag::Paragraph),
+            ]
+        );
+    }
+
+    #[test]
+    fn too_deep_tags_left_out() {
+        let quotes = MAX_DEPTH + 2;
+        let events = (0..quotes)
+            .map(|_| Event::Start(Tag::BlockQuote))
+            .chain([Event::Text(CowStr::Borrowed("a"))])
+            .chain((0..quotes).map(|_| Event::End(Tag::BlockQuote)));
+        let balanced: Vec<_> = balanced(events).collect();
+        let starts = balanced
+            .iter()
+            .filter(|e| matches!(e, Event::Start(_)))
+            .count();
+        let ends = balanced
+            .iter()
+            .filter(|e| matches!(e, Event::End(_)))
+            .count();
+        assert_eq!((starts, ends), (MAX_DEPTH, MAX_DEPTH));
+        let middle = &balanced[MAX_DEPTH..balanced.len() - MAX_DEPTH];
+        assert_eq!(
+            middle,
+            [
+                Event::HardBreak,
+                Event::HardBreak,
+                Event::Text(CowStr::Borrowed("a"))
+            ]
+        );
+    }
+
+    #[test]
+    fn slugs_like_github() {
+        let mut slugs = HashMap::new();
+        assert_eq!(slug("Hello, World!", &mut slugs), "hello-world");
+        assert_eq!(slug("Hello World", &mut slugs), "hello-world-1");
+        assert_eq!(slug("snake_case & co-op", &mut slugs), "snake_case--co-op");
+    }
+
+    #[test]
+    fn section_numbers() {
+        assert_eq!(section_number(&[2]), "2");
+        assert_eq!(section_number(&[1, 0, 3]), "1.0.3");
+    }
+
+    #[test]
+    fn titles_compared_loosely() {
+        assert_eq!(normalize("Set-up & Usage!"), "setupusage");
+    }
+
+    #[test]
+    #[cfg(feature = "emoji")]
+    fn shortcodes_expanded() {
+        assert_eq!(
+            expand_shortcodes("Nice :smile: but :nope: at 10:30:00").as_deref(),
+            Some("Nice 😄 but :nope: at 10:30:00")
+        );
+        assert_eq!(expand_shortcodes("no :nope: here"), None);
+    }
 }
@@ -0,0 +1,627 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Output backends - the final step turning styled text into terminal output
+
+use crate::dombox::{DomStyle, Palette, WidthPolicy};
+use crate::Background;
+use ansi_term::Style;
+use std::io;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Something able to output lines of styled text
+pub trait Backend {
+    /// Output some text with the given style
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()>;
+    /// Terminate the current line
+    fn end_line(&mut self) -> io::Result<()>;
+}
+
+/// Output through ansi_term - this is the default one
+pub struct AnsiTerm<W: io::Write> {
+    out: W,
+    /// Style of the last text written on the current line
+    last: Option<Style>,
+}
+
+impl<W: io::Write> AnsiTerm<W> {
+    pub fn new(out: W) -> Self {
+        AnsiTerm { out, last: None }
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: io::Write> Backend for AnsiTerm<W> {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        // nothing to show, nor to color
+        if text.is_empty() {
+            return Ok(());
+        }
+        // only output what changed since the previous text of the line
+        let astyle = style.to_ansi();
+        match self.last.replace(astyle) {
+            Some(last) => write!(self.out, "{}", last.infix(astyle))?,
+            None => write!(self.out, "{}", astyle.prefix())?,
+        }
+        match style.link {
+            Some(ref link) => write_hyperlink(&mut self.out, link, text),
+            None => self.out.write_all(text.as_bytes()),
+        }
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        if let Some(last) = self.last.take() {
+            write!(self.out, "{}", last.suffix())?;
+        }
+        writeln!(self.out)
+    }
+}
+
+/// Output of the bare text, without any escape sequence - the layout is kept
+/// with the characters alone, for emails or pagers without colors
+pub struct PlainText<W: io::Write> {
+    out: W,
+    /// The current line, whose trailing spaces are only there for backgrounds
+    line: String,
+}
+
+impl<W: io::Write> PlainText<W> {
+    pub fn new(out: W) -> Self {
+        PlainText {
+            out,
+            line: String::new(),
+        }
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: io::Write> Backend for PlainText<W> {
+    fn write_styled(&mut self, _style: &DomStyle, text: &str) -> io::Result<()> {
+        self.line.push_str(text);
+        Ok(())
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        writeln!(self.out, "{}", self.line.trim_end())?;
+        self.line.clear();
+        Ok(())
+    }
+}
+
+/// One column of a line of the rendered document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// The grapheme shown there, empty in the column on the right of a wide one
+    pub symbol: String,
+    pub style: DomStyle,
+}
+
+impl Default for Cell {
+    /// A space without any style
+    fn default() -> Self {
+        Cell {
+            symbol: " ".to_string(),
+            style: DomStyle::default(),
+        }
+    }
+}
+
+/// Output into a grid of cells, one per column, for the programs drawing the
+/// document themselves
+pub struct Grid {
+    widths: WidthPolicy,
+    rows: Vec<Vec<Cell>>,
+    line: Vec<Cell>,
+}
+
+impl Grid {
+    /// The columns taken by the graphemes are told by `widths`, as in the layout
+    pub fn new(widths: WidthPolicy) -> Self {
+        Grid {
+            widths,
+            rows: vec![],
+            line: vec![],
+        }
+    }
+    /// The lines output, as long as their texts
+    pub fn into_rows(self) -> Vec<Vec<Cell>> {
+        self.rows
+    }
+}
+
+impl Backend for Grid {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        for grapheme in text.graphemes(true) {
+            let width = self.widths.width(grapheme);
+            match (width, self.line.last_mut()) {
+                // combining characters go with the previous grapheme
+                (0, Some(last)) => last.symbol.push_str(grapheme),
+                (0, None) => {}
+                _ => {
+                    self.line.push(Cell {
+                        symbol: grapheme.to_string(),
+                        style: style.clone(),
+                    });
+                    for _ in 1..width {
+                        self.line.push(Cell {
+                            symbol: String::new(),
+                            style: style.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        self.rows.push(std::mem::take(&mut self.line));
+        Ok(())
+    }
+}
+
+/// The RGB colors of a terminal, for the backends drawing it
+struct Screen {
+    palette: Palette,
+    /// The terminal's own colors, for the text without any
+    fg: [u8; 3],
+    bg: [u8; 3],
+}
+
+impl Screen {
+    /// The terminal's own colors are taken in the palette, black or white
+    /// depending on the background
+    fn new(palette: Palette, background: Background) -> Self {
+        let (fg, bg) = match background {
+            Background::Dark => (palette.get(7), palette.get(0)),
+            Background::Light => (palette.get(0), palette.get(15)),
+        };
+        Screen { palette, fg, bg }
+    }
+    /// The foreground and background of a style, when they're not the terminal's
+    fn colors(&self, style: &DomStyle) -> (Option<[u8; 3]>, Option<[u8; 3]>) {
+        let rgb = |idx: Option<u8>| idx.map(|idx| self.palette.get(idx));
+        let (fg, bg) = (rgb(style.fg.index()), rgb(style.bg.index()));
+        match style.reverse {
+            true => (Some(bg.unwrap_or(self.bg)), Some(fg.unwrap_or(self.fg))),
+            false => (fg, bg),
+        }
+    }
+}
+
+/// Output as HTML meant for a `<pre>`, the colors being those of a palette
+pub struct Html<W: io::Write> {
+    out: W,
+    screen: Screen,
+    /// CSS of the `<span>` left open, continued by texts in the same style
+    open: Option<String>,
+}
+
+impl<W: io::Write> Html<W> {
+    /// The text without colors is in black or white depending on the background
+    pub fn new(out: W, palette: Palette, background: Background) -> Self {
+        Html {
+            out,
+            screen: Screen::new(palette, background),
+            open: None,
+        }
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+    /// Start a standalone page, with the `<pre>` the lines go into
+    pub fn begin_page(&mut self) -> io::Result<()> {
+        writeln!(self.out, "<!DOCTYPE html>")?;
+        writeln!(self.out, "<html>")?;
+        writeln!(self.out, "<head>")?;
+        writeln!(self.out, "<meta charset=\"utf-8\">")?;
+        writeln!(self.out, "<title>catmark</title>")?;
+        writeln!(self.out, "</head>")?;
+        writeln!(
+            self.out,
+            "<body style=\"margin: 0; background: {}\">",
+            css(self.screen.bg)
+        )?;
+        writeln!(
+            self.out,
+            "<pre style=\"margin: 0; padding: 1em; line-height: 1.2; color: {}; background: {}\">",
+            css(self.screen.fg),
+            css(self.screen.bg)
+        )
+    }
+    /// End the page started by `begin_page`
+    pub fn end_page(&mut self) -> io::Result<()> {
+        writeln!(self.out, "</pre>")?;
+        writeln!(self.out, "</body>")?;
+        writeln!(self.out, "</html>")
+    }
+    fn close_span(&mut self) -> io::Result<()> {
+        match self.open.take() {
+            Some(_) => write!(self.out, "</span>"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: io::Write> Backend for Html<W> {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (fg, bg) = self.screen.colors(style);
+        let mut props = vec![];
+        props.extend(fg.map(|fg| format!("color: {}", css(fg))));
+        props.extend(bg.map(|bg| format!("background: {}", css(bg))));
+        if style.bold {
+            props.push("font-weight: bold".to_string());
+        }
+        if style.italic {
+            props.push("font-style: italic".to_string());
+        }
+        let decorations: Vec<_> = [
+            (style.underline, "underline"),
+            (style.strikethrough, "line-through"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, decoration)| *decoration)
+        .collect();
+        if !decorations.is_empty() {
+            props.push(format!("text-decoration: {}", decorations.join(" ")));
+        }
+        let props = props.join("; ");
+        if self.open.as_ref() != Some(&props) || style.link.is_some() {
+            self.close_span()?;
+            if let Some(ref link) = style.link {
+                write!(
+                    self.out,
+                    "<a href=\"{}\" style=\"color: inherit\">",
+                    escape(link)
+                )?;
+            }
+            if !props.is_empty() {
+                write!(self.out, "<span style=\"{}\">", props)?;
+                self.open = Some(props);
+            }
+        }
+        write!(self.out, "{}", escape(text))?;
+        if style.link.is_some() {
+            self.close_span()?;
+            write!(self.out, "</a>")?;
+        }
+        Ok(())
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        self.close_span()?;
+        writeln!(self.out)
+    }
+}
+
+/// Width and height of a character cell in an SVG image, for a 14px monospace font
+const CELL: (f32, f32) = (8.4, 17.0);
+
+/// Output as an SVG image of the terminal, the colors being those of a palette
+pub struct Svg<W: io::Write> {
+    out: W,
+    screen: Screen,
+    widths: WidthPolicy,
+    /// The backgrounds and texts drawn, written once the size of the image is known
+    body: String,
+    /// Cell where the next text goes
+    row: usize,
+    col: usize,
+    /// Widest line so far, in cells
+    cols: usize,
+}
+
+impl<W: io::Write> Svg<W> {
+    /// The text without colors is in black or white depending on the background,
+    /// the cells it takes are told by `widths` as in the layout
+    pub fn new(out: W, palette: Palette, background: Background, widths: WidthPolicy) -> Self {
+        Svg {
+            out,
+            screen: Screen::new(palette, background),
+            widths,
+            body: String::new(),
+            row: 0,
+            col: 0,
+            cols: 0,
+        }
+    }
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+    /// Write the whole image, with what was drawn since the start
+    pub fn end_image(&mut self) -> io::Result<()> {
+        let (width, height) = (self.cols as f32 * CELL.0, self.row as f32 * CELL.1);
+        writeln!(
+            self.out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.1}\" height=\"{:.1}\" \
+             font-family=\"monospace\" font-size=\"14\" xml:space=\"preserve\">",
+            width, height
+        )?;
+        writeln!(
+            self.out,
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+            css(self.screen.bg)
+        )?;
+        self.out
+            .write_all(std::mem::take(&mut self.body).as_bytes())?;
+        writeln!(self.out, "</svg>")
+    }
+}
+
+impl<W: io::Write> Backend for Svg<W> {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        use std::fmt::Write;
+        let width = self.widths.width(text);
+        if width == 0 {
+            return Ok(());
+        }
+        let (x, y) = (self.col as f32 * CELL.0, self.row as f32 * CELL.1);
+        let (fg, bg) = self.screen.colors(style);
+        if let Some(bg) = bg {
+            let _ = writeln!(
+                self.body,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                x,
+                y,
+                width as f32 * CELL.0,
+                CELL.1,
+                css(bg)
+            );
+        }
+        self.col += width;
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        let mut attrs = format!(" fill=\"{}\"", css(fg.unwrap_or(self.screen.fg)));
+        if style.bold {
+            attrs.push_str(" font-weight=\"bold\"");
+        }
+        if style.italic {
+            attrs.push_str(" font-style=\"italic\"");
+        }
+        let decorations: Vec<_> = [
+            (style.underline, "underline"),
+            (style.strikethrough, "line-through"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, decoration)| *decoration)
+        .collect();
+        if !decorations.is_empty() {
+            let _ = write!(attrs, " text-decoration=\"{}\"", decorations.join(" "));
+        }
+        // the glyphs are stretched to the cells, whatever the actual font
+        let text = format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" textLength=\"{:.1}\" lengthAdjust=\"spacingAndGlyphs\"{}>{}</text>",
+            x,
+            // on the baseline, about 4/5 down the cell
+            y + CELL.1 * 0.8,
+            width as f32 * CELL.0,
+            attrs,
+            escape(text)
+        );
+        let _ = match style.link {
+            Some(ref link) => writeln!(self.body, "<a href=\"{}\">{}</a>", escape(link), text),
+            None => writeln!(self.body, "{}", text),
+        };
+        Ok(())
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        self.cols = self.cols.max(self.col);
+        self.col = 0;
+        self.row += 1;
+        Ok(())
+    }
+}
+
+/// A CSS color, like `#c0c0c0`
+fn css([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Text made safe to put in HTML or SVG, in elements or attributes
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write the text wrapped in OSC 8 sequences making it a hyperlink
+fn write_hyperlink<W: io::Write>(out: &mut W, link: &str, text: &str) -> io::Result<()> {
+    out.write_all(b"\x1b]8;;")?;
+    // a control character would end the sequence early, and start another
+    for c in link.chars() {
+        if c.is_control() {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                write!(out, "%{:02X}", byte)?;
+            }
+        } else {
+            write!(out, "{}", c)?;
+        }
+    }
+    write!(out, "\x1b\\{}\x1b]8;;\x1b\\", text)
+}
+
+#[cfg(feature = "termcolor")]
+impl From<&DomStyle> for termcolor::ColorSpec {
+    fn from(style: &DomStyle) -> Self {
+        let (mut fg, mut bg) = (style.fg.index(), style.bg.index());
+        if style.reverse {
+            // termcolor can't reverse the video, swap the colors of a dark terminal
+            (fg, bg) = (Some(bg.unwrap_or(0)), Some(fg.unwrap_or(7)));
+        }
+        let mut spec = termcolor::ColorSpec::new();
+        spec.set_fg(fg.map(termcolor::Color::Ansi256))
+            .set_bg(bg.map(termcolor::Color::Ansi256))
+            .set_bold(style.bold)
+            .set_underline(style.underline)
+            .set_strikethrough(style.strikethrough)
+            .set_italic(style.italic);
+        spec
+    }
+}
+
+/// Output through any termcolor `WriteColor`
+#[cfg(feature = "termcolor")]
+pub struct TermColor<W: termcolor::WriteColor>(pub W);
+
+#[cfg(feature = "termcolor")]
+impl<W: termcolor::WriteColor> Backend for TermColor<W> {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.0.set_color(&style.into())?;
+        if let Some(ref link) = style.link {
+            self.0
+                .set_hyperlink(&termcolor::HyperlinkSpec::open(link.as_bytes()))?;
+            self.0.write_all(text.as_bytes())?;
+            self.0.set_hyperlink(&termcolor::HyperlinkSpec::close())?;
+        } else {
+            self.0.write_all(text.as_bytes())?;
+        }
+        self.0.reset()
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        writeln!(self.0)
+    }
+}
+
+#[cfg(feature = "anstyle")]
+impl From<&DomStyle> for anstyle::Style {
+    fn from(style: &DomStyle) -> Self {
+        let mut astyle = anstyle::Style::new()
+            .fg_color(
+                style
+                    .fg
+                    .index()
+                    .map(|idx| anstyle::Ansi256Color(idx).into()),
+            )
+            .bg_color(
+                style
+                    .bg
+                    .index()
+                    .map(|idx| anstyle::Ansi256Color(idx).into()),
+            );
+        if style.bold {
+            astyle = astyle.bold();
+        }
+        if style.underline {
+            astyle = astyle.underline();
+        }
+        if style.strikethrough {
+            astyle = astyle.strikethrough();
+        }
+        if style.italic {
+            astyle = astyle.italic();
+        }
+        if style.reverse {
+            astyle = astyle.invert();
+        }
+        astyle
+    }
+}
+
+/// Output anstyle-styled text to any writer
+#[cfg(feature = "anstyle")]
+pub struct Anstyle<W: io::Write>(pub W);
+
+#[cfg(feature = "anstyle")]
+impl<W: io::Write> Backend for Anstyle<W> {
+    fn write_styled(&mut self, style: &DomStyle, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let astyle = anstyle::Style::from(style);
+        write!(self.0, "{}", astyle.render())?;
+        match style.link {
+            Some(ref link) => write_hyperlink(&mut self.0, link, text)?,
+            None => self.0.write_all(text.as_bytes())?,
+        }
+        write!(self.0, "{}", astyle.render_reset())
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        writeln!(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_texts_leave_no_escapes() {
+        let bold = DomStyle {
+            bold: true,
+            ..Default::default()
+        };
+        let mut term = AnsiTerm::new(vec![]);
+        term.write_styled(&bold, "").unwrap();
+        term.end_line().unwrap();
+        assert_eq!(term.into_inner(), b"\n");
+    }
+
+    #[test]
+    fn hyperlinks_without_controls() {
+        let link = DomStyle {
+            link: Some("http://a\x1b]2;pwned\x07b\u{9c}".to_string()),
+            ..Default::default()
+        };
+        let mut term = AnsiTerm::new(vec![]);
+        term.write_styled(&link, "x").unwrap();
+        let out = String::from_utf8(term.into_inner()).unwrap();
+        assert!(
+            out.contains("\x1b]8;;http://a%1B]2;pwned%07b%C2%9C\x1b\\x\x1b]8;;\x1b\\"),
+            "{:?}",
+            out
+        );
+    }
+
+    #[test]
+    fn html_spans_merged_and_escaped() {
+        let bold = DomStyle {
+            bold: true,
+            ..Default::default()
+        };
+        let link = DomStyle {
+            link: Some("https://example.com/?a&b".to_string()),
+            ..Default::default()
+        };
+        let mut html = Html::new(vec![], Palette::default(), Background::Dark);
+        html.write_styled(&bold, "a<").unwrap();
+        html.write_styled(&bold, "&b").unwrap();
+        html.write_styled(&link, "c").unwrap();
+        html.end_line().unwrap();
+        let html = String::from_utf8(html.into_inner()).unwrap();
+        assert_eq!(
+            html,
+            "<span style=\"font-weight: bold\">a&lt;&amp;b</span>\
+             <a href=\"https://example.com/?a&amp;b\" style=\"color: inherit\">c</a>\n"
+        );
+    }
+
+    #[test]
+    fn grid_cells_of_wide_graphemes() {
+        let style = DomStyle::default();
+        let mut grid = Grid::new(WidthPolicy::default());
+        grid.write_styled(&style, "漢e\u{301}").unwrap();
+        grid.end_line().unwrap();
+        let rows = grid.into_rows();
+        let symbols: Vec<_> = rows[0].iter().map(|cell| &*cell.symbol).collect();
+        assert_eq!(symbols, ["漢", "", "e\u{301}"]);
+    }
+}
@@ -4,24 +4,153 @@
 
 //! DOM for ANSI terminal rendering
 
+use crate::backend::Backend;
 pub use crate::xy::XY;
-use ansi_term::{ANSIString, ANSIStrings};
 use ansi_term::{Colour, Style};
 use pulldown_cmark::CowStr;
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io;
+use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const MIN_WIDTH: XY = XY::new(1);
 const MIN_HEIGHT: XY = XY::new(1);
+/// Blank runs up to this wide are borrowed from here rather than allocated
+const SPACES: &str = concat!(
+    "                                                                ",
+    "                                                                ",
+);
+/// Lines narrower than this don't get a wrap marker, it would leave no room for text
+const WRAP_MARKER_MIN_WIDTH: XY = XY::new(4);
+/// Blocks and list items narrower than this lose their decorations and indents
+/// to make room for their content, e.g. in narrow terminal panes
+const NARROW_WIDTH: XY = XY::new(4);
 
-fn findsplit(s: &str, pos: usize) -> usize {
-    if let Some(n) = UnicodeSegmentation::grapheme_indices(s, true).nth(pos) {
-        return n.0;
+/// Colors of the outlines of the boxes, by depth
+const OUTLINE_COLORS: [TermColor; 6] = [
+    TermColor::Red,
+    TermColor::Green,
+    TermColor::Yellow,
+    TermColor::Blue,
+    TermColor::Purple,
+    TermColor::Cyan,
+];
+
+/// How wide a cluster of characters (emoji sequence, flag...) is
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ClusterWidth {
+    /// As Unicode tells: "👨‍👩‍👧" or "🇫🇷" take two columns, like in terminals
+    /// handling grapheme clusters
+    #[default]
+    Unicode,
+    /// The sum of its characters, like in terminals using `wcwidth()`: "👨‍👩‍👧"
+    /// takes six columns and "❤️" only one
+    Terminal,
+}
+
+/// How many columns the terminal gives to characters
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct WidthPolicy {
+    /// Characters of ambiguous East Asian width, like "α" or "①", are wide - as
+    /// in most CJK terminals
+    pub ambiguous_wide: bool,
+    pub clusters: ClusterWidth,
+}
+
+impl WidthPolicy {
+    /// Number of columns taken by `s`
+    pub fn width(&self, s: &str) -> usize {
+        match (self.clusters, self.ambiguous_wide) {
+            (ClusterWidth::Unicode, false) => UnicodeWidthStr::width(s),
+            (ClusterWidth::Unicode, true) => UnicodeWidthStr::width_cjk(s),
+            (ClusterWidth::Terminal, false) => s.chars().filter_map(|c| c.width()).sum(),
+            (ClusterWidth::Terminal, true) => s.chars().filter_map(|c| c.width_cjk()).sum(),
+        }
+    }
+}
+
+/// Byte index where to cut `s` so that its beginning fits in `cols` columns,
+/// wide characters like emoji or CJK counting for two
+fn findsplit(s: &str, cols: usize, widths: WidthPolicy) -> usize {
+    let mut width = 0;
+    for (i, grapheme) in UnicodeSegmentation::grapheme_indices(s, true) {
+        width += widths.width(grapheme);
+        if width > cols {
+            return i;
+        }
     }
     s.len()
 }
 
+/// Where to cut a text too long for `cols` columns, and whether a hyphen must be
+/// added there
+fn findcut(s: &str, cols: usize, style: &DomStyle, widths: WidthPolicy) -> (usize, bool) {
+    let pos = findsplit(s, cols, widths);
+    if style.url {
+        if let Some(slash) = s[..pos].rfind(['/', '?']) {
+            return (slash + 1, false);
+        }
+    }
+    #[cfg(feature = "hyphenate")]
+    if style.hyphenate == Some(true) {
+        if let Some(cut) = syllable_cut(s, pos, cols, widths) {
+            return cut;
+        }
+    }
+    (pos, false)
+}
+
+/// If `pos` is inside a word, the last of its syllable boundaries leaving room
+/// for a hyphen in `cols` columns, or else its start - tells if a hyphen is needed
+#[cfg(feature = "hyphenate")]
+fn syllable_cut(s: &str, pos: usize, cols: usize, widths: WidthPolicy) -> Option<(usize, bool)> {
+    use hyphenation::{Hyphenator, Language, Load, Standard};
+    thread_local! {
+        static ENGLISH: Standard = Standard::from_embedded(Language::EnglishUS)
+            .expect("embedded hyphenation patterns are valid");
+    }
+    let (before, after) = s.split_at(pos);
+    if !before.ends_with(is_letter) || !after.starts_with(is_letter) {
+        return None;
+    }
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !is_letter(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = pos + after.find(|c| !is_letter(c)).unwrap_or(after.len());
+    let syllable = ENGLISH.with(|english| {
+        english
+            .hyphenate(&s[start..end])
+            .breaks
+            .iter()
+            .rev()
+            .map(|b| start + b)
+            .filter(|&cut| !s[cut..].starts_with(is_combining))
+            .find(|&cut| cut < pos && widths.width(&s[..cut]) < cols)
+    });
+    match syllable {
+        Some(cut) => Some((cut, true)),
+        None if start > 0 => Some((start, false)),
+        None => None,
+    }
+}
+
+/// Combining marks are part of the word of the letter they're on, like the accent
+/// of a decomposed "é"
+#[cfg(feature = "hyphenate")]
+fn is_letter(c: char) -> bool {
+    c.is_alphabetic() || is_combining(c)
+}
+
+#[cfg(feature = "hyphenate")]
+fn is_combining(c: char) -> bool {
+    c != '\u{200b}' && UnicodeWidthChar::width(c) == Some(0)
+}
+
 pub fn split_at_in_place<'a>(cow: &mut CowStr<'a>, mid: usize) -> CowStr<'a> {
     match *cow {
         CowStr::Boxed(_) | CowStr::Inlined(_) => {
@@ -39,6 +168,7 @@ pub fn split_at_in_place<'a>(cow: &mut CowStr<'a>, mid: usize) -> CowStr<'a> {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
 pub enum TermColor {
     Black,
     Red,
@@ -51,15 +181,25 @@ pub enum TermColor {
 }
 
 /// Full color definition
-#[derive(Debug, Default, Clone)]
-pub struct DomColor(Option<u8>); // TODO enum (None, Simple(u8), Full(u8,u8,u8))
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
+pub enum DomColor {
+    /// The parent's color, the terminal's for the root
+    #[default]
+    Inherited,
+    /// The terminal's own foreground or background, whatever the parent's - e.g. to
+    /// let a transparent background show
+    Terminal,
+    /// One of the 256 colors
+    Indexed(u8),
+}
 
 impl DomColor {
     pub fn from_dark(color: TermColor) -> DomColor {
-        DomColor(Some(color as u8))
+        DomColor::Indexed(color as u8)
     }
     pub fn from_light(color: TermColor) -> DomColor {
-        DomColor(Some(color as u8 + 8))
+        DomColor::Indexed(color as u8 + 8)
     }
     pub fn from_grey(level: u8) -> DomColor {
         let mut level = level >> 4;
@@ -68,23 +208,109 @@ impl DomColor {
             15 => 231,
             grey => 231 + grey,
         };
-        DomColor(Some(level))
+        DomColor::Indexed(level)
     }
+    /// The closest color of xterm's default palette
     pub fn from_color_lo(red: u8, green: u8, blue: u8) -> DomColor {
-        if (red >> 4) == (green >> 4) && (green >> 4) == (blue >> 4) {
-            return DomColor::from_grey(red);
-        }
-        let red = (red as u32 * 6 / 256) as u8;
-        let green = (green as u32 * 6 / 256) as u8;
-        let blue = (blue as u32 * 6 / 256) as u8;
-        DomColor(Some(16 + red * 36 + green * 6 + blue))
+        DomColor::from_rgb(red, green, blue, &Palette::default())
+    }
+    /// The closest color of a terminal's palette
+    pub fn from_rgb(red: u8, green: u8, blue: u8, palette: &Palette) -> DomColor {
+        DomColor::Indexed(palette.nearest([red, green, blue]))
     }
+    /// The color from the 256 ones, if it's not the terminal's
     pub fn index(&self) -> Option<u8> {
-        self.0
+        match *self {
+            DomColor::Indexed(index) => Some(index),
+            _ => None,
+        }
+    }
+    /// This color isn't inherited
+    pub fn is_set(&self) -> bool {
+        *self != DomColor::Inherited
+    }
+}
+
+/// Levels of the channels in the 6x6x6 color cube of 256 color terminals
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// xterm's 16 base colors
+const BASE_COLORS: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0xcd, 0x00, 0x00],
+    [0x00, 0xcd, 0x00],
+    [0xcd, 0xcd, 0x00],
+    [0x00, 0x00, 0xee],
+    [0xcd, 0x00, 0xcd],
+    [0x00, 0xcd, 0xcd],
+    [0xe5, 0xe5, 0xe5],
+    [0x7f, 0x7f, 0x7f],
+    [0xff, 0x00, 0x00],
+    [0x00, 0xff, 0x00],
+    [0xff, 0xff, 0x00],
+    [0x5c, 0x5c, 0xff],
+    [0xff, 0x00, 0xff],
+    [0x00, 0xff, 0xff],
+    [0xff, 0xff, 0xff],
+];
+
+/// The RGB values of the 256 colors of a terminal - the 16 base ones are often
+/// customized, so they're only picked to approximate a color once they're set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: [[u8; 3]; 256],
+    /// Which base colors are the terminal's actual ones, one bit each
+    known_base: u16,
+}
+
+impl Default for Palette {
+    /// xterm's palette
+    fn default() -> Self {
+        let mut colors = [[0; 3]; 256];
+        colors[..16].copy_from_slice(&BASE_COLORS);
+        for (i, color) in colors[16..232].iter_mut().enumerate() {
+            *color = [i / 36, i / 6 % 6, i % 6].map(|level| CUBE_LEVELS[level]);
+        }
+        for (i, color) in colors[232..].iter_mut().enumerate() {
+            *color = [8 + 10 * i as u8; 3];
+        }
+        Palette {
+            colors,
+            known_base: 0,
+        }
     }
 }
 
-#[derive(Debug, Clone, Default)]
+impl Palette {
+    /// Tell the actual RGB value of a color
+    pub fn set(&mut self, index: u8, rgb: [u8; 3]) {
+        self.colors[usize::from(index)] = rgb;
+        if index < 16 {
+            self.known_base |= 1 << index;
+        }
+    }
+    pub fn get(&self, index: u8) -> [u8; 3] {
+        self.colors[usize::from(index)]
+    }
+    /// The index of the color looking the closest to `rgb`, by the "redmean"
+    /// approximation of the perceived distance
+    pub fn nearest(&self, rgb: [u8; 3]) -> u8 {
+        let distance = |color: [u8; 3]| {
+            let [r1, g1, b1] = rgb.map(i32::from);
+            let [r2, g2, b2] = color.map(i32::from);
+            let red_mean = (r1 + r2) / 2;
+            let (dr, dg, db) = (r1 - r2, g1 - g2, b1 - b2);
+            (((512 + red_mean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - red_mean) * db * db) >> 8)
+        };
+        let known = (0..=255u8).filter(|&index| index >= 16 || self.known_base & 1 << index != 0);
+        known
+            .min_by_key(|&index| distance(self.get(index)))
+            .expect("the color cube is always known")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub enum TextAlign {
     #[default]
     Left,
@@ -92,7 +318,8 @@ pub enum TextAlign {
     Right,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub enum BorderType {
     #[default]
     Empty,
@@ -100,19 +327,139 @@ pub enum BorderType {
     Thin,
     Double,
     Bold,
+    /// `>` on the sides, like in e-mail replies
+    Quote,
+    /// Thin, with rounded corners
+    Rounded,
+    BoldDash,
+    /// `+`, `-` and `|`, for terminals lacking box drawing characters
+    Ascii,
+    /// This character everywhere, e.g. for horizontal rules
+    Custom(char),
+    /// Characters of one's own
+    Set(BorderSet),
 }
 
-/// This is where the appearance of everything is stored - each element should have one
-#[derive(Debug, Default, Clone)]
+/// The characters borders are drawn with
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
+pub struct BorderSet {
+    pub top_left: char,
+    pub top_right: char,
+    pub bottom_left: char,
+    pub bottom_right: char,
+    pub horizontal: char,
+    pub vertical: char,
+    /// Where borders meet, like in tables
+    pub top_tee: char,
+    pub bottom_tee: char,
+    pub left_tee: char,
+    pub right_tee: char,
+    pub cross: char,
+}
+
+impl BorderSet {
+    /// The set from its 11 characters in this order: corners "┌┐└┘", lines "─│",
+    /// then tees "┬┴├┤┼"
+    pub const fn new(chars: [char; 11]) -> BorderSet {
+        BorderSet {
+            top_left: chars[0],
+            top_right: chars[1],
+            bottom_left: chars[2],
+            bottom_right: chars[3],
+            horizontal: chars[4],
+            vertical: chars[5],
+            top_tee: chars[6],
+            bottom_tee: chars[7],
+            left_tee: chars[8],
+            right_tee: chars[9],
+            cross: chars[10],
+        }
+    }
+    /// The set from a string of exactly 11 characters, in the order of `new`
+    pub fn parse(chars: &str) -> Option<BorderSet> {
+        let chars: Vec<char> = chars.chars().collect();
+        Some(BorderSet::new(chars.try_into().ok()?))
+    }
+}
+
+impl BorderType {
+    /// The characters this border is drawn with
+    pub fn chars(self) -> BorderSet {
+        match self {
+            BorderType::Empty => BorderSet::new([' '; 11]),
+            BorderType::Dash => {
+                BorderSet::new(['┌', '┐', '└', '┘', '╌', '╎', '┬', '┴', '├', '┤', '┼'])
+            }
+            BorderType::Thin => {
+                BorderSet::new(['┌', '┐', '└', '┘', '─', '│', '┬', '┴', '├', '┤', '┼'])
+            }
+            BorderType::Double => {
+                BorderSet::new(['╔', '╗', '╚', '╝', '═', '║', '╦', '╩', '╠', '╣', '╬'])
+            }
+            BorderType::Bold => {
+                BorderSet::new(['┏', '┓', '┗', '┛', '━', '┃', '┳', '┻', '┣', '┫', '╋'])
+            }
+            BorderType::BoldDash => {
+                BorderSet::new(['┏', '┓', '┗', '┛', '┅', '┇', '┳', '┻', '┣', '┫', '╋'])
+            }
+            BorderType::Rounded => {
+                BorderSet::new(['╭', '╮', '╰', '╯', '─', '│', '┬', '┴', '├', '┤', '┼'])
+            }
+            BorderType::Quote => {
+                BorderSet::new(['>', '>', '>', '>', '-', '>', '-', '-', '>', '>', '-'])
+            }
+            BorderType::Ascii => {
+                BorderSet::new(['+', '+', '+', '+', '-', '|', '+', '+', '+', '+', '+'])
+            }
+            BorderType::Custom(c) => BorderSet::new([c; 11]),
+            BorderType::Set(set) => set,
+        }
+    }
+}
+
+/// What to do with text too long for its line
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
+pub enum Overflow {
+    /// Continue it on the next line
+    #[default]
+    Wrap,
+    /// Cut it at the edge with a marker, dropping the rest of the line
+    Truncate,
+}
+
+/// This is where the appearance of everything is stored - each element has its own,
+/// where what's left unset (default colors, attributes off, left alignment...) is
+/// inherited from its parent when laid out, except what's about its borders and width
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct DomStyle {
     pub bg: DomColor,
     pub fg: DomColor,
+    /// Colors of the borders, when they're not those of the content
+    pub border_bg: DomColor,
+    pub border_fg: DomColor,
     pub bold: bool,
     pub underline: bool,
     pub strikethrough: bool,
     pub italic: bool,
+    /// Swap the foreground and background colors
+    pub reverse: bool,
     pub extend: bool,
+    pub overflow: Overflow,
+    /// Start lines continuing a wrapped one with a "↪ " marker
+    pub wrap_marker: bool,
+    /// Target of a hyperlink, for terminals supporting them (OSC 8)
+    pub link: Option<String>,
+    /// Cut words that don't fit at a syllable, with a hyphen (needs the
+    /// `hyphenate` feature) - inherited when `None`
+    pub hyphenate: Option<bool>,
+    /// This is a URL, better cut after a "/" or "?" to keep it readable
+    pub url: bool,
     pub align: TextAlign,
+    /// Percentage of the available width taken by an extended block
+    pub width: Option<u16>,
     pub border_type: BorderType,
     pub top_nb_type: BorderType,
     pub bottom_nb_type: BorderType,
@@ -121,6 +468,72 @@ pub struct DomStyle {
 }
 
 impl DomStyle {
+    /// The style of our borders: ours, with the border colors if they're set
+    fn border(&self) -> DomStyle {
+        let mut style = self.clone();
+        if self.border_fg.is_set() {
+            style.fg = self.border_fg.clone();
+        }
+        if self.border_bg.is_set() {
+            style.bg = self.border_bg.clone();
+        }
+        style
+    }
+    /// What the children get of this style, before applying theirs: all but the
+    /// borders and what makes the width
+    fn inherited(&self) -> DomStyle {
+        DomStyle {
+            border_bg: DomColor::default(),
+            border_fg: DomColor::default(),
+            extend: false,
+            width: None,
+            border_type: BorderType::Empty,
+            top_nb_type: BorderType::Empty,
+            bottom_nb_type: BorderType::Empty,
+            left_nb_type: BorderType::Empty,
+            right_nb_type: BorderType::Empty,
+            ..self.clone()
+        }
+    }
+    /// Overlay another style on this one: its colors, alignment, border type and
+    /// link replace ours when they're set, and its attributes are added to ours
+    pub fn apply(&mut self, other: &DomStyle) {
+        if other.fg.is_set() {
+            self.fg = other.fg.clone();
+        }
+        if other.bg.is_set() {
+            self.bg = other.bg.clone();
+        }
+        if other.border_fg.is_set() {
+            self.border_fg = other.border_fg.clone();
+        }
+        if other.border_bg.is_set() {
+            self.border_bg = other.border_bg.clone();
+        }
+        self.bold |= other.bold;
+        self.underline |= other.underline;
+        self.strikethrough |= other.strikethrough;
+        self.italic |= other.italic;
+        self.reverse |= other.reverse;
+        self.extend |= other.extend;
+        self.wrap_marker |= other.wrap_marker;
+        if other.hyphenate.is_some() {
+            self.hyphenate = other.hyphenate;
+        }
+        self.url |= other.url;
+        if let Overflow::Truncate = other.overflow {
+            self.overflow = other.overflow;
+        }
+        if other.align != TextAlign::Left {
+            self.align = other.align;
+        }
+        if !matches!(other.border_type, BorderType::Empty) {
+            self.border_type = other.border_type;
+        }
+        if other.link.is_some() {
+            self.link = other.link.clone();
+        }
+    }
     pub fn to_ansi(&self) -> Style {
         let mut astyle = Style::new();
         match self.fg.index() {
@@ -147,12 +560,86 @@ impl DomStyle {
         if self.italic {
             astyle = astyle.italic();
         }
+        if self.reverse {
+            astyle = astyle.reverse();
+        }
         astyle
     }
 }
 
+/// A piece of rendered text with its style, borrowed from the boxes when possible
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledStr<'a> {
+    pub style: Cow<'a, DomStyle>,
+    pub text: Cow<'a, str>,
+}
+
+impl<'a> StyledStr<'a> {
+    pub fn new(style: &'a DomStyle, text: impl Into<Cow<'a, str>>) -> StyledStr<'a> {
+        StyledStr {
+            style: Cow::Borrowed(style),
+            text: text.into(),
+        }
+    }
+    /// Nothing shows, not even a background color
+    fn is_blank(&self) -> bool {
+        self.text.trim().is_empty() && self.style.bg.index().is_none()
+    }
+}
+
+/// Cleanups of the rendered lines, for output going to files or pagers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Tidy {
+    /// Drop the spaces ending lines, unless they have a background color
+    pub trim_trailing: bool,
+    /// At most this many blank lines in a row
+    pub max_blank_lines: Option<usize>,
+}
+
+/// Keep only `keep` columns of the spans, after their `skip` first ones, measured
+/// as told by `widths` - a wide character cut in two leaves spaces
+fn clip_spans(spans: &mut [StyledStr], skip: XY, keep: XY, widths: WidthPolicy) {
+    let (skip, keep) = (usize::from(skip), usize::from(keep));
+    let mut col = 0;
+    for span in spans {
+        let mut text = String::new();
+        for grapheme in span.text.graphemes(true) {
+            let (start, end) = (col, col + widths.width(grapheme));
+            col = end;
+            if start >= skip && end <= skip + keep {
+                text.push_str(grapheme);
+            } else {
+                let shown = end.min(skip + keep).saturating_sub(start.max(skip));
+                text.extend(std::iter::repeat_n(' ', shown));
+            }
+        }
+        span.text = text.into();
+    }
+}
+
+/// Remove the spaces ending a line without a background color
+fn trim_trailing(spans: &mut Vec<StyledStr>) {
+    while let Some(last) = spans.last_mut() {
+        if last.style.bg.index().is_some() {
+            break;
+        }
+        last.text = match std::mem::take(&mut last.text) {
+            Cow::Borrowed(text) => Cow::Borrowed(text.trim_end()),
+            Cow::Owned(mut text) => {
+                text.truncate(text.trim_end().len());
+                Cow::Owned(text)
+            }
+        };
+        if !last.text.is_empty() {
+            break;
+        }
+        spans.pop();
+    }
+}
+
 /// A layouting element kind - which type of "box" is it
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub enum BoxKind<'a> {
     /// Some text (an inline element)
     Text(CowStr<'a>),
@@ -164,8 +651,8 @@ pub enum BoxKind<'a> {
     Inline,
     /// A simple rectangular block
     Block,
-    /// A title with a specific "weigth"
-    Header(u8),
+    /// A title with a specific "weigth", and maybe an id to link to it
+    Header(u8, Option<CowStr<'a>>),
     /// An ordered list with a start number
     List(Option<XY>),
     /// An unordered list
@@ -186,6 +673,12 @@ struct BoxCursor {
     container: BoxSize,
     x: XY,
     y: XY,
+    widths: WidthPolicy,
+    /// Cut the lines too long for the container
+    wrap: bool,
+    /// Merge the margins of the first and last children with the box's - not
+    /// for the root, nothing is outside it
+    collapse: bool,
 }
 
 impl fmt::Display for BoxCursor {
@@ -208,14 +701,83 @@ impl fmt::Display for BoxCursor {
 }
 
 /// This is a bounding box: coordinates + size + borders
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct BoxSize {
     pub content: Rect,
     pub border: Edges,
+    /// Blank space between the border and the content, in the box's background -
+    /// for blocks only
+    pub padding: Edges,
+    /// Blank space outside the border, in the parent's background - for blocks
+    /// only, and the bottom margin of a block merges with the top one of the next
+    pub margin: Edges,
+    /// Bounds of the content width of a block, as far as the room allows
+    pub min_width: Option<XY>,
+    pub max_width: Option<XY>,
+}
+
+impl BoxSize {
+    /// Thicknesses of what surrounds the content: borders and padding
+    pub fn inset(&self) -> Edges {
+        Edges {
+            top: self.border.top + self.padding.top,
+            bottom: self.border.bottom + self.padding.bottom,
+            left: self.border.left + self.padding.left,
+            right: self.border.right + self.padding.right,
+        }
+    }
+    /// Columns taken on the sides of the content: margins, borders and padding
+    fn sides(&self) -> XY {
+        self.inset().left + self.inset().right + self.margin.left + self.margin.right
+    }
+    /// Drop what's on the sides until the content gets at least `NARROW_WIDTH`
+    /// of the `room` columns: the borders first, then the padding, then the margins
+    fn shed_sides(&mut self, room: XY) {
+        let steps: [fn(&mut BoxSize) -> &mut Edges; 3] = [
+            |size| &mut size.border,
+            |size| &mut size.padding,
+            |size| &mut size.margin,
+        ];
+        for edges in steps {
+            if room.saturating_sub(self.sides()) >= NARROW_WIDTH {
+                return;
+            }
+            let edges = edges(self);
+            edges.left = 0.into();
+            edges.right = 0.into();
+        }
+    }
+}
+
+/// Widths of columns sharing `room`: those narrower than an even share keep
+/// their `natural` width, the others evenly share what's left - and none gets
+/// narrower than `MIN_WIDTH`, even with no room
+fn share_width(natural: &[XY], room: XY) -> Vec<XY> {
+    if natural.iter().fold(XY::new(0), |sum, &width| sum + width) <= room {
+        return natural.to_vec();
+    }
+    let mut widths = natural.to_vec();
+    let mut order: Vec<usize> = (0..natural.len()).collect();
+    order.sort_by_key(|&column| natural[column]);
+    let mut left = room;
+    for (done, &column) in order.iter().enumerate() {
+        let share = left / XY::saturating_from(natural.len() - done);
+        widths[column] = natural[column].min(share).max(MIN_WIDTH);
+        left = left.saturating_sub(widths[column]);
+    }
+    widths
+}
+
+/// Lines between two sibling blocks: the bigger of their margins - `prev` is
+/// the bottom margin of the previous one
+fn margin_gap(prev: XY, child: &DomBox) -> XY {
+    prev.max(child.collapsed_top())
 }
 
 /// Coordinates and side for a rectangle (a box)
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct Rect {
     x: XY,
     y: XY,
@@ -224,7 +786,8 @@ pub struct Rect {
 }
 
 /// Thicknesses of borders
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct Edges {
     pub top: XY,
     pub bottom: XY,
@@ -241,10 +804,13 @@ enum LayoutRes<T> {
     CutHere(T),
     /// Couldn't make anything fit, invalid layout
     Reject,
+    /// Had to truncate, what follows on the same line must be dropped
+    Full,
 }
 
 /// The main layouting element
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct DomBox<'a> {
     pub kind: BoxKind<'a>,
     pub size: BoxSize,
@@ -258,6 +824,16 @@ impl<'a> DomBox<'a> {
         dombox.size.content.w = width;
         dombox
     }
+    /// Change the width available to a root box - it needs to be laid out again afterwards
+    pub fn set_width(&mut self, width: XY) {
+        self.size.content.w = width;
+    }
+    /// Leave blank columns on the sides of a root box, inside its width
+    pub fn set_margins(&mut self, left: XY, right: XY) {
+        self.size.border.left = left;
+        self.size.border.right = right;
+        self.style.border_type = BorderType::Empty;
+    }
     pub fn new_block() -> DomBox<'a> {
         DomBox {
             size: Default::default(),
@@ -282,7 +858,7 @@ impl<'a> DomBox<'a> {
                         self.children.push(DomBox {
                             size: Default::default(),
                             kind: BoxKind::InlineContainer,
-                            style: self.style.clone(),
+                            style: Default::default(),
                             children: vec![],
                         });
                     }
@@ -296,7 +872,7 @@ impl<'a> DomBox<'a> {
         inline_container.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::Text(text),
-            style: inline_container.style.clone(),
+            style: Default::default(),
             children: vec![],
         });
         inline_container.children.last_mut().unwrap()
@@ -306,7 +882,7 @@ impl<'a> DomBox<'a> {
         inline_container.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::Inline,
-            style: inline_container.style.clone(),
+            style: Default::default(),
             children: vec![],
         });
         inline_container.children.last_mut().unwrap()
@@ -315,16 +891,16 @@ impl<'a> DomBox<'a> {
         self.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::Block,
-            style: self.style.clone(),
+            style: Default::default(),
             children: vec![],
         });
         self.children.last_mut().unwrap()
     }
-    pub fn add_header(&mut self, level: u8) -> &mut DomBox<'a> {
+    pub fn add_header(&mut self, level: u8, id: Option<CowStr<'a>>) -> &mut DomBox<'a> {
         self.children.push(DomBox {
             size: Default::default(),
-            kind: BoxKind::Header(level),
-            style: self.style.clone(),
+            kind: BoxKind::Header(level, id),
+            style: Default::default(),
             children: vec![],
         });
         self.children.last_mut().unwrap()
@@ -333,7 +909,7 @@ impl<'a> DomBox<'a> {
         self.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::List(start),
-            style: self.style.clone(),
+            style: Default::default(),
             children: vec![],
         });
         self.children.last_mut().unwrap()
@@ -342,7 +918,38 @@ impl<'a> DomBox<'a> {
         self.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::ListBullet,
-            style: self.style.clone(),
+            style: Default::default(),
+            children: vec![],
+        });
+        self.children.last_mut().unwrap()
+    }
+    pub fn add_table(&mut self) -> &mut DomBox<'a> {
+        self.children.push(DomBox {
+            size: Default::default(),
+            kind: BoxKind::Table,
+            style: Default::default(),
+            children: vec![],
+        });
+        self.children.last_mut().unwrap()
+    }
+    pub fn add_table_column(&mut self) -> &mut DomBox<'a> {
+        self.children.push(DomBox {
+            size: Default::default(),
+            kind: BoxKind::TableColumn,
+            style: Default::default(),
+            children: vec![],
+        });
+        self.children.last_mut().unwrap()
+    }
+    /// A cell, as wide as its column
+    pub fn add_table_item(&mut self) -> &mut DomBox<'a> {
+        self.children.push(DomBox {
+            size: Default::default(),
+            kind: BoxKind::TableItem,
+            style: DomStyle {
+                extend: true,
+                ..Default::default()
+            },
             children: vec![],
         });
         self.children.last_mut().unwrap()
@@ -351,207 +958,829 @@ impl<'a> DomBox<'a> {
         self.children.push(DomBox {
             size: Default::default(),
             kind: BoxKind::Break,
-            style: self.style.clone(),
+            style: Default::default(),
             children: vec![],
         });
         self.children.last_mut().unwrap()
     }
+    /// All the text in the box, without any styling
+    pub fn text(&self) -> String {
+        match self.kind {
+            BoxKind::Text(ref text) => text.to_string(),
+            _ => self.children.iter().map(DomBox::text).collect(),
+        }
+    }
+    /// Frame the blocks, lists and bullets with thin borders colored by their depth,
+    /// to see how they get laid out
+    pub fn outline(&mut self) {
+        self.outline_from(0);
+    }
+    fn outline_from(&mut self, depth: usize) {
+        if let BoxKind::Text(_) | BoxKind::Inline | BoxKind::InlineContainer = self.kind {
+            return;
+        }
+        let border = &mut self.size.border;
+        for edge in [
+            &mut border.top,
+            &mut border.bottom,
+            &mut border.left,
+            &mut border.right,
+        ] {
+            *edge = (*edge).max(1.into());
+        }
+        self.style.border_type = BorderType::Thin;
+        self.style.border_fg = DomColor::from_light(OUTLINE_COLORS[depth % OUTLINE_COLORS.len()]);
+        for child in &mut self.children {
+            child.outline_from(depth + 1);
+        }
+    }
+    /// One line per box, indented by depth: its kind then the position and size
+    /// of its content - for layout debugging
+    pub fn box_tree(&self) -> String {
+        let mut tree = String::new();
+        self.write_box_tree(0, &mut tree);
+        tree
+    }
+    fn write_box_tree(&self, depth: usize, tree: &mut String) {
+        let kind = match self.kind {
+            BoxKind::Text(ref text) if text.chars().count() > 20 => {
+                format!("Text {:?}…", text.chars().take(20).collect::<String>())
+            }
+            BoxKind::Text(ref text) => format!("Text {:?}", &**text),
+            BoxKind::Header(level, _) => format!("Header({})", level),
+            BoxKind::List(Some(start)) => format!("List({})", start),
+            BoxKind::List(None) => "List".to_string(),
+            ref kind => format!("{:?}", kind),
+        };
+        let content = self.size.content;
+        tree.push_str(&format!(
+            "{:indent$}{} at {},{} {}x{}\n",
+            "",
+            kind,
+            content.x,
+            content.y,
+            content.w,
+            content.h,
+            indent = 2 * depth
+        ));
+        for child in &self.children {
+            child.write_box_tree(depth + 1, tree);
+        }
+    }
+    /// Put `style` over the parts of the texts found by `find`, e.g. search matches -
+    /// it gets the whole text of each inline container, the line breaks being
+    /// newlines, so a match may span several differently styled texts
+    pub fn restyle_matches<F: Fn(&str) -> Vec<Range<usize>>>(
+        &mut self,
+        find: &F,
+        style: &DomStyle,
+    ) {
+        if let BoxKind::InlineContainer = self.kind {
+            let mut text = String::new();
+            self.plain_text(&mut text);
+            let matches = find(&text);
+            if !matches.is_empty() {
+                self.restyle_ranges(&matches, &mut 0, style);
+            }
+        } else {
+            for child in &mut self.children {
+                child.restyle_matches(find, style);
+            }
+        }
+    }
+    /// Append the text in the box to `out`, with a newline for each line break
+    fn plain_text(&self, out: &mut String) {
+        match self.kind {
+            BoxKind::Text(ref text) => out.push_str(text),
+            BoxKind::Break => out.push('\n'),
+            _ => self.children.iter().for_each(|child| child.plain_text(out)),
+        }
+    }
+    /// Restyle the parts of our texts in the byte `ranges` of the `plain_text`,
+    /// which is at `offset` where we start - our texts get cut at their edges
+    fn restyle_ranges(&mut self, ranges: &[Range<usize>], offset: &mut usize, style: &DomStyle) {
+        for mut child in std::mem::take(&mut self.children) {
+            let BoxKind::Text(ref mut text) = child.kind else {
+                if let BoxKind::Break = child.kind {
+                    *offset += 1;
+                } else {
+                    child.restyle_ranges(ranges, offset, style);
+                }
+                self.children.push(child);
+                continue;
+            };
+            let (start, end) = (*offset, *offset + text.len());
+            *offset = end;
+            let overlapping = ranges.iter().filter(|r| r.start < end && r.end > start);
+            let sides = child.size.border.left + child.size.border.right;
+            if sides != 0.into() {
+                // a framed text like a keycap stays in one piece
+                if overlapping.count() > 0 {
+                    child.style.apply(style);
+                }
+                self.children.push(child);
+                continue;
+            }
+            let mut rest = std::mem::replace(text, CowStr::Borrowed(""));
+            let mut pos = start;
+            for range in overlapping {
+                let (from, to) = (range.start.max(start), range.end.min(end));
+                for (cut, marked) in [(from, false), (to, true)] {
+                    let tail = split_at_in_place(&mut rest, cut - pos);
+                    let head = std::mem::replace(&mut rest, tail);
+                    pos = cut;
+                    if !head.is_empty() {
+                        let mut piece = child.clone();
+                        piece.kind = BoxKind::Text(head);
+                        if marked {
+                            piece.style.apply(style);
+                        }
+                        self.children.push(piece);
+                    }
+                }
+            }
+            if !rest.is_empty() {
+                child.kind = BoxKind::Text(rest);
+                self.children.push(child);
+            }
+        }
+    }
+    /// First line of the heading with the given id, once laid out
+    pub fn anchor_line(&self, id: &str) -> Option<usize> {
+        match self.kind {
+            BoxKind::Header(_, Some(ref anchor)) if **anchor == *id => {
+                Some((self.size.content.y - self.size.inset().top).into())
+            }
+            _ => self.children.iter().find_map(|child| child.anchor_line(id)),
+        }
+    }
     pub fn layout(&mut self) {
+        self.layout_with(WidthPolicy::default());
+    }
+    /// Give the boxes the style they inherit, with theirs over it - a box changed
+    /// after its children were added still passes its changes on to them
+    fn inherit_styles(&mut self, parent: &DomStyle) {
+        let mut style = parent.inherited();
+        style.apply(&self.style);
+        self.style = style;
+        for child in &mut self.children {
+            child.inherit_styles(&self.style);
+        }
+    }
+    /// Lay the box out, measuring texts as told by `widths`
+    pub fn layout_with(&mut self, widths: WidthPolicy) {
+        self.inherit_styles(&DomStyle::default());
         let mut cursor = BoxCursor {
             x: 0.into(),
             y: 0.into(),
             container: self.size,
+            widths,
+            wrap: true,
+            collapse: false,
         };
         self.layout_generic(&mut cursor);
     }
-    fn inline_children_loop(
-        &mut self,
-        res: LayoutRes<DomBox<'a>>,
-        dorej: bool,
-    ) -> LayoutRes<DomBox<'a>> {
-        let mut res = res;
+    /// Lay the box out without wrapping its lines: it gets as wide as the
+    /// longest one
+    pub fn layout_unwrapped(&mut self, widths: WidthPolicy) {
+        self.inherit_styles(&DomStyle::default());
+        self.size.content.w = self.natural_width(widths);
+        let mut cursor = BoxCursor {
+            x: 0.into(),
+            y: 0.into(),
+            container: self.size,
+            widths,
+            wrap: false,
+            collapse: false,
+        };
+        self.layout_generic(&mut cursor);
+    }
+    /// Lay a root box out again, keeping its first `from` children where they are:
+    /// they must be the same as in the previous layout, made with the same width
+    pub fn layout_from(&mut self, from: usize, widths: WidthPolicy) {
+        self.inherit_styles(&DomStyle::default());
+        let mut cursor = BoxCursor {
+            x: 0.into(),
+            y: 0.into(),
+            container: self.size,
+            widths,
+            wrap: true,
+            collapse: false,
+        };
+        self.layout_block_from(from, &mut cursor);
+    }
+    /// The same box, owning all its texts
+    pub fn into_owned(self) -> DomBox<'static> {
+        let owned = |text: CowStr| CowStr::Boxed(text.into_string().into_boxed_str());
+        DomBox {
+            kind: match self.kind {
+                BoxKind::Text(text) => BoxKind::Text(owned(text)),
+                BoxKind::Header(level, id) => BoxKind::Header(level, id.map(owned)),
+                BoxKind::Break => BoxKind::Break,
+                BoxKind::InlineContainer => BoxKind::InlineContainer,
+                BoxKind::Inline => BoxKind::Inline,
+                BoxKind::Block => BoxKind::Block,
+                BoxKind::List(start) => BoxKind::List(start),
+                BoxKind::ListBullet => BoxKind::ListBullet,
+                BoxKind::Table => BoxKind::Table,
+                BoxKind::TableColumn => BoxKind::TableColumn,
+                BoxKind::TableItem => BoxKind::TableItem,
+                BoxKind::Image => BoxKind::Image,
+            },
+            size: self.size,
+            style: self.style,
+            children: self.children.into_iter().map(DomBox::into_owned).collect(),
+        }
+    }
+    /// Lay the box out and tell how many terminal lines it will occupy
+    pub fn measure(&mut self) -> usize {
+        self.layout();
+        self.height().into()
+    }
+    /// Width of the box with its sides, when none of its lines is wrapped
+    fn natural_width(&self, widths: WidthPolicy) -> XY {
+        let content = match self.kind {
+            BoxKind::Text(ref text) => XY::saturating_from(widths.width(text)),
+            BoxKind::InlineContainer | BoxKind::Inline => {
+                // the longest of the lines between the breaks
+                let (mut longest, mut line) = (XY::new(0), XY::new(0));
+                for child in &self.children {
+                    if let BoxKind::Break = child.kind {
+                        longest = longest.max(line);
+                        line = 0.into();
+                    } else {
+                        line += child.natural_width(widths);
+                    }
+                }
+                longest.max(line)
+            }
+            BoxKind::List(_) => {
+                // the items hang after their bullets
+                let (mut widest, mut bullet) = (XY::new(0), XY::new(0));
+                for child in &self.children {
+                    if let BoxKind::ListBullet = child.kind {
+                        bullet = child.natural_width(widths);
+                    } else {
+                        widest = widest.max(bullet + child.natural_width(widths));
+                        bullet = 0.into();
+                    }
+                }
+                widest.max(bullet)
+            }
+            // the columns side by side, a rule between them
+            BoxKind::Table => {
+                let columns = self
+                    .children
+                    .iter()
+                    .map(|child| child.natural_width(widths));
+                let rules = XY::saturating_from(self.children.len().saturating_sub(1));
+                columns.fold(rules, |width, column| width + column)
+            }
+            _ => self
+                .children
+                .iter()
+                .map(|child| child.natural_width(widths))
+                .max()
+                .unwrap_or_default(),
+        };
+        self.size.min_width.map_or(content, |min| content.max(min)) + self.size.sides()
+    }
+    fn inline_children_loop(&mut self, cursor: &BoxCursor) -> LayoutRes<DomBox<'a>> {
+        let mut res = LayoutRes::Normal;
         let mut subcursor = BoxCursor {
             x: self.size.content.x,
             y: self.size.content.y,
             container: self.size,
+            widths: cursor.widths,
+            wrap: cursor.wrap,
+            collapse: true,
         };
-        let mut i = 0;
-        while i < self.children.len() {
-            if let BoxKind::Break = self.children[i].kind {
-                self.children.remove(i);
-                res = LayoutRes::CutHere(DomBox {
-                    kind: self.kind.clone(),
-                    size: self.size.clone(),
-                    style: self.style.clone(),
-                    children: self.children.split_off(i),
-                });
+        // the children laid out go back to us one by one, the rest of them to the
+        // piece cut after us
+        let mut queue: VecDeque<DomBox<'a>> = std::mem::take(&mut self.children).into();
+        while let Some(mut child) = queue.pop_front() {
+            if let BoxKind::Break = child.kind {
+                res = LayoutRes::CutHere(self.rest(queue));
                 break;
             }
-            match self.children[i].layout_generic(&mut subcursor) {
-                LayoutRes::Normal => (),
+            match child.layout_generic(&mut subcursor) {
+                LayoutRes::Normal => self.children.push(child),
                 LayoutRes::CutHere(next) => {
-                    self.children.insert(i + 1, next);
-                    res = LayoutRes::CutHere(DomBox {
-                        kind: self.kind.clone(),
-                        size: self.size.clone(),
-                        style: self.style.clone(),
-                        children: self.children.split_off(i + 1),
-                    });
+                    self.children.push(child);
+                    queue.push_front(next);
+                    res = LayoutRes::CutHere(self.rest(queue));
                     break;
                 }
-                LayoutRes::Reject => {
-                    if i == 0 {
-                        if dorej {
-                            res = LayoutRes::Reject;
-                        } else {
-                            panic!("can't reject from first {:?}", self.children[i].kind);
-                        }
-                    } else {
-                        res = LayoutRes::CutHere(DomBox {
-                            kind: self.kind.clone(),
-                            size: self.size.clone(),
-                            style: self.style.clone(),
-                            children: self.children.split_off(i),
-                        });
+                LayoutRes::Full => {
+                    self.children.push(child);
+                    // drop everything up to the next line break, if any
+                    while queue
+                        .front()
+                        .is_some_and(|next| !matches!(next.kind, BoxKind::Break))
+                    {
+                        queue.pop_front();
+                    }
+                    if queue.is_empty() {
+                        res = LayoutRes::Full;
+                        break;
                     }
+                }
+                LayoutRes::Reject if self.children.is_empty() => {
+                    queue.push_front(child);
+                    self.children = queue.into();
+                    res = LayoutRes::Reject;
+                    break;
+                }
+                LayoutRes::Reject => {
+                    queue.push_front(child);
+                    res = LayoutRes::CutHere(self.rest(queue));
                     break;
                 }
             }
-            i += 1;
         }
         self.size.content.w = subcursor.x - self.size.content.x;
         res
     }
+    /// A box like us with the children left after a cut - in a list of their
+    /// own size, not of the one they come from
+    fn rest(&self, children: VecDeque<DomBox<'a>>) -> DomBox<'a> {
+        DomBox {
+            kind: self.kind.clone(),
+            size: self.size,
+            style: self.style.clone(),
+            children: children.into_iter().collect(),
+        }
+    }
+    /// Nothing of the box shows but its children, which already have its style
+    fn is_bare(&self) -> bool {
+        self.size.sides() == 0.into()
+            && self.size.inset().top == 0.into()
+            && self.size.inset().bottom == 0.into()
+            && self.size.min_width.is_none()
+            && self.size.max_width.is_none()
+    }
     fn layout_generic(&mut self, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
-        let res = match self.kind {
-            BoxKind::Block | BoxKind::ListBullet | BoxKind::Header(_) => self.layout_block(cursor),
-            BoxKind::InlineContainer => self.layout_inline_container(cursor),
+        match self.kind {
             BoxKind::List(_) => self.layout_list(cursor),
+            BoxKind::Table => self.layout_table(cursor),
             BoxKind::Text(_) | BoxKind::Inline => self.layout_inline(cursor),
-            BoxKind::Break => panic!("shouldn't layout a break"),
-            _ => panic!("unimplemented layout for {:?}", self.kind),
-        };
-        res
+            // inline containers are laid out by their block, give this stray one its own
+            BoxKind::InlineContainer => {
+                let container = std::mem::replace(self, DomBox::new_block());
+                self.style = container.style.inherited();
+                self.children.push(container);
+                self.layout_block(cursor)
+            }
+            // the breaks end the lines of their inline container, elsewhere they're nothing
+            BoxKind::Break => {
+                self.size.content = Rect {
+                    x: cursor.x,
+                    y: cursor.y,
+                    ..Default::default()
+                };
+                LayoutRes::Normal
+            }
+            _ => self.layout_block(cursor),
+        }
     }
     fn layout_block(&mut self, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
+        self.layout_block_from(0, cursor)
+    }
+    /// Lay the block out, its children before the `from`th one being already
+    /// laid out there
+    fn layout_block_from(&mut self, from: usize, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
         let res = LayoutRes::Normal;
-        self.size.content.x = cursor.x + self.size.border.left;
-        self.size.content.y = cursor.y + self.size.border.top;
+        // what's left of the container's width - the cursor may be past its end
+        let left =
+            (cursor.container.content.x + cursor.container.content.w).saturating_sub(cursor.x);
+        if cursor.wrap {
+            self.size.shed_sides(left);
+        }
+        self.size.content.x = cursor.x + self.size.margin.left + self.size.inset().left;
+        self.size.content.y = cursor.y + self.size.inset().top;
         self.size.content.h = 0.into();
-        self.size.content.w = if cursor.container.content.w - cursor.x + cursor.container.content.x
-            > self.size.border.left + self.size.border.right
-        {
-            cursor.container.content.w - cursor.x + cursor.container.content.x
-                - self.size.border.left
-                - self.size.border.right
+        let room = if left > self.size.sides() {
+            left - self.size.sides()
         } else {
             MIN_WIDTH
         };
+        self.size.content.w = self.size.max_width.map_or(room, |max| room.min(max));
         let mut subcursor = BoxCursor {
             x: self.size.content.x,
             y: self.size.content.y,
             container: self.size,
+            widths: cursor.widths,
+            wrap: cursor.wrap,
+            collapse: true,
         };
-        let mut max_width = 0.into();
-        let mut i = 0;
-        while i < self.children.len() {
-            if let BoxKind::Break = self.children[i].kind {
-                self.children.remove(i);
+        let mut max_width: XY = 0.into();
+        let mut margin = 0.into();
+        let collapse_top = cursor.collapse && self.size.inset().top == 0.into();
+        if let Some(last) = self.children[..from].last() {
+            let bottom = last.bottom();
+            self.size.content.h = bottom - self.size.content.y;
+            subcursor.y = bottom;
+            margin = last.size.margin.bottom;
+            for child in &self.children[..from] {
+                max_width = max_width.max(child.size.content.w + child.size.sides());
+            }
+        }
+        // the laid out children (and pieces of them) go to a new list, in one pass
+        let mut children = std::mem::take(&mut self.children).into_iter();
+        self.children.extend(children.by_ref().take(from));
+        for child in children {
+            if let BoxKind::Break = child.kind {
                 continue;
             }
-            match self.children[i].layout_generic(&mut subcursor) {
-                LayoutRes::Normal => (),
-                LayoutRes::CutHere(next) => self.children.insert(i + 1, next),
-                LayoutRes::Reject => {
-                    panic!("can't reject a {:?}", self.children[i].kind);
-                }
+            // the top margin of the first child is part of ours
+            if !(self.children.is_empty() && collapse_top) {
+                let gap = margin_gap(margin, &child);
+                subcursor.y += gap;
+                self.size.content.h += gap;
             }
-            self.size.content.h += self.children[i].size.content.h
-                + self.children[i].size.border.top
-                + self.children[i].size.border.bottom;
-            if self.children[i].size.content.w
-                + self.children[i].size.border.left
-                + self.children[i].size.border.right
-                > max_width
-            {
-                max_width = self.children[i].size.content.w
-                    + self.children[i].size.border.left
-                    + self.children[i].size.border.right;
+            let first = self.children.len();
+            if let BoxKind::InlineContainer = child.kind {
+                child.layout_lines(&mut subcursor, &mut self.children);
+            } else {
+                child.layout_pieces(&mut subcursor, &mut self.children);
             }
-            i += 1;
+            for piece in &self.children[first..] {
+                self.size.content.h += piece.height();
+                max_width = max_width.max(piece.size.content.w + piece.size.sides());
+            }
+            margin = self
+                .children
+                .last()
+                .map_or(margin, |piece| piece.size.margin.bottom);
         }
-        if !self.style.extend {
+        self.end_margin(margin, cursor);
+        // aligned children need room to move in, unless we're aligned ourselves
+        let aligned = self.style.align == TextAlign::Left
+            && self
+                .children
+                .iter()
+                .any(|child| child.style.align != TextAlign::Left);
+        if !self.style.extend && !aligned {
             self.size.content.w = max_width;
+        } else if let Some(percent) = self.style.width {
+            let width = usize::from(self.size.content.w) * usize::from(percent.min(100)) / 100;
+            self.size.content.w = XY::try_from(width).expect("narrower than before");
+        }
+        if let Some(min) = self.size.min_width {
+            self.size.content.w = self.size.content.w.max(min.min(room));
         }
+        self.align_children(from);
         if let BoxKind::ListBullet = self.kind {
             // XXX ugly
-            cursor.x += self.size.content.w + self.size.border.left + self.size.border.right;
+            cursor.x += self.size.content.w + self.size.sides();
         } else {
             cursor.x = cursor.container.content.x;
-            cursor.y += self.size.content.h + self.size.border.top + self.size.border.bottom;
+            cursor.y += self.size.content.h + self.size.inset().top + self.size.inset().bottom;
         }
         res
     }
+    /// Take the bottom margin of the last child as ours when nothing separates
+    /// them, else keep it inside
+    fn end_margin(&mut self, margin: XY, cursor: &BoxCursor) {
+        if cursor.collapse && self.size.inset().bottom == 0.into() {
+            self.size.margin.bottom = self.size.margin.bottom.max(margin);
+        } else {
+            self.size.content.h += margin;
+        }
+    }
+    /// Top margin of the box, merged with the ones of its first children when
+    /// nothing separates them
+    fn collapsed_top(&self) -> XY {
+        let first = self
+            .children
+            .iter()
+            .find(|child| !matches!(child.kind, BoxKind::Break));
+        match (&self.kind, first) {
+            (BoxKind::Block | BoxKind::Header(..) | BoxKind::List(_), Some(first))
+                if self.size.inset().top == 0.into() =>
+            {
+                self.size.margin.top.max(first.collapsed_top())
+            }
+            _ => self.size.margin.top,
+        }
+    }
+    /// Move the centered or right-aligned children narrower than us, now that our
+    /// width is known - from the `from`th one, the previous ones already are
+    fn align_children(&mut self, from: usize) {
+        let width = self.size.content.w;
+        for child in &mut self.children[from..] {
+            let used = child.size.content.w + child.size.sides();
+            if used >= width {
+                continue;
+            }
+            match child.style.align {
+                TextAlign::Left => {}
+                TextAlign::Center => child.shift_x((width - used) / 2),
+                TextAlign::Right => child.shift_x(width - used),
+            }
+        }
+    }
+    /// Move an already laid out box to the right, with its content
+    fn shift_x(&mut self, dx: XY) {
+        self.size.content.x += dx;
+        for child in &mut self.children {
+            child.shift_x(dx);
+        }
+    }
     fn layout_list(&mut self, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
         let res = LayoutRes::Normal;
-        self.size.content.w =
-            if cursor.container.content.w > self.size.border.left + self.size.border.right {
-                cursor.container.content.w - self.size.border.left - self.size.border.right
-            } else {
-                MIN_WIDTH
-            };
+        self.size.content.w = if cursor.container.content.w > self.size.sides() {
+            cursor.container.content.w - self.size.sides()
+        } else {
+            MIN_WIDTH
+        };
+        if let Some(max) = self.size.max_width {
+            self.size.content.w = self.size.content.w.min(max);
+        }
         self.size.content.h = 0.into();
-        self.size.content.x = cursor.x + self.size.border.left;
-        self.size.content.y = cursor.y + self.size.border.top;
+        self.size.content.x = cursor.x + self.size.margin.left + self.size.inset().left;
+        self.size.content.y = cursor.y + self.size.inset().top;
         let mut subcursor = BoxCursor {
             x: self.size.content.x,
             y: self.size.content.y,
             container: self.size,
+            widths: cursor.widths,
+            wrap: cursor.wrap,
+            collapse: true,
         };
-        let mut i = 0;
-        while i < self.children.len() {
-            match self.children[i].kind {
-                BoxKind::ListBullet => match self.children[i].layout_generic(&mut subcursor) {
-                    LayoutRes::Normal => (),
-                    LayoutRes::CutHere(next) => self.children.insert(i + 1, next),
-                    LayoutRes::Reject => {
-                        panic!("can't reject a {:?}", self.children[i].kind);
+        let mut margin = 0.into();
+        let collapse_top = cursor.collapse && self.size.inset().top == 0.into();
+        for child in std::mem::take(&mut self.children) {
+            // the top margin of the first child is part of ours
+            if !(self.children.is_empty() && collapse_top) {
+                let gap = margin_gap(margin, &child);
+                subcursor.y += gap;
+                self.size.content.h += gap;
+            }
+            let first = self.children.len();
+            match child.kind {
+                BoxKind::ListBullet => {
+                    child.layout_pieces(&mut subcursor, &mut self.children);
+                    // too narrow to hang the item after its bullet: put it below
+                    let end = self.size.content.x + self.size.content.w;
+                    if cursor.wrap && end.saturating_sub(subcursor.x) < NARROW_WIDTH {
+                        let height = self.children[first..].iter().map(DomBox::height).max();
+                        let height = height.unwrap_or(MIN_HEIGHT);
+                        subcursor.x = self.size.content.x;
+                        subcursor.y += height;
+                        self.size.content.h += height;
                     }
-                },
+                }
                 BoxKind::Block => {
-                    match self.children[i].layout_generic(&mut subcursor) {
-                        LayoutRes::Normal => (),
-                        LayoutRes::CutHere(next) => self.children.insert(i + 1, next),
-                        LayoutRes::Reject => {
-                            panic!("can't reject a {:?}", self.children[i].kind);
-                        }
+                    child.layout_pieces(&mut subcursor, &mut self.children);
+                    for piece in &self.children[first..] {
+                        self.size.content.h += piece.height();
+                    }
+                }
+                // content outside of an item, e.g. from made up events: it gets a
+                // block like the items
+                _ => {
+                    let mut block = DomBox::new_block();
+                    block.style = self.style.inherited();
+                    block.children.push(child);
+                    block.layout_pieces(&mut subcursor, &mut self.children);
+                    for piece in &self.children[first..] {
+                        self.size.content.h += piece.height();
                     }
-                    self.size.content.h += self.children[i].size.content.h
-                        + self.children[i].size.border.top
-                        + self.children[i].size.border.bottom;
                 }
-                _ => panic!("can't layout a {:?} in a List", self.children[i].kind),
             }
-            i += 1;
+            margin = self
+                .children
+                .last()
+                .map_or(margin, |piece| piece.size.margin.bottom);
         }
-        cursor.y += self.size.content.h + self.size.border.top + self.size.border.bottom;
+        self.end_margin(margin, cursor);
+        cursor.y += self.size.content.h + self.size.inset().top + self.size.inset().bottom;
         res
     }
-    // this is a line, and when split will be 2 lines
-    fn layout_inline_container(&mut self, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
-        let mut res = LayoutRes::Normal;
-        self.size.content.w =
-            if cursor.container.content.w > self.size.border.left + self.size.border.right {
-                cursor.container.content.w - self.size.border.left - self.size.border.right
-            } else {
-                MIN_WIDTH
+    /// Every child a column of as many cells as the others, whatever events
+    /// the table was built from
+    fn fill_table(&mut self) {
+        // the styles were already inherited
+        let style = self.style.inherited();
+        for child in &mut self.children {
+            if !matches!(child.kind, BoxKind::TableColumn) {
+                let mut column = DomBox::new_block();
+                column.kind = BoxKind::TableColumn;
+                column.style = style.clone();
+                let item = column.add_table_item();
+                item.style.apply(&style);
+                item.children
+                    .push(std::mem::replace(child, DomBox::new_block()));
+                *child = column;
+            }
+        }
+        let rows = self
+            .children
+            .iter()
+            .map(|column| column.children.len())
+            .max();
+        for column in &mut self.children {
+            while column.children.len() < rows.unwrap_or(0) {
+                column.add_table_item().style.apply(&style);
+            }
+        }
+    }
+    /// Lay the columns out side by side, a rule between them and another under
+    /// the first row, the headings: they share the room as evenly as their
+    /// widths allow, and the cells of a row all get the height of the highest
+    fn layout_table(&mut self, cursor: &mut BoxCursor) -> LayoutRes<DomBox<'a>> {
+        self.fill_table();
+        if self.children.is_empty() {
+            return self.layout_block(cursor);
+        }
+        let left =
+            (cursor.container.content.x + cursor.container.content.w).saturating_sub(cursor.x);
+        if cursor.wrap {
+            self.size.shed_sides(left);
+        }
+        self.size.content.x = cursor.x + self.size.margin.left + self.size.inset().left;
+        self.size.content.y = cursor.y + self.size.inset().top;
+        let rules = XY::saturating_from(self.children.len() - 1);
+        let natural: Vec<XY> = self
+            .children
+            .iter()
+            .map(|column| column.natural_width(cursor.widths))
+            .collect();
+        let widths = match cursor.wrap {
+            true => share_width(&natural, left.saturating_sub(self.size.sides() + rules)),
+            false => natural.clone(),
+        };
+        let rows = self.children[0].children.len();
+        let mut y = self.size.content.y;
+        for row in 0..rows {
+            // the rule under the headings
+            if row == 1 {
+                y += 1;
+            }
+            let mut x = self.size.content.x;
+            let mut height = MIN_HEIGHT;
+            let columns = self.children.iter_mut().zip(&widths).zip(&natural);
+            for ((column, &width), &natural) in columns {
+                let cell = &mut column.children[row];
+                let mut subcursor = BoxCursor {
+                    x,
+                    y,
+                    container: BoxSize {
+                        content: Rect {
+                            x,
+                            y,
+                            w: width,
+                            h: 0.into(),
+                        },
+                        ..Default::default()
+                    },
+                    widths: cursor.widths,
+                    // in a column as wide as its content, it keeps its sides
+                    wrap: cursor.wrap && width < natural,
+                    collapse: false,
+                };
+                // a block is never cut
+                cell.layout_generic(&mut subcursor);
+                height = height.max(cell.height());
+                x += width + 1;
+            }
+            for column in &mut self.children {
+                let cell = &mut column.children[row];
+                cell.size.content.h = height - cell.size.inset().top - cell.size.inset().bottom;
+            }
+            y += height;
+        }
+        let mut x = self.size.content.x;
+        for (column, &width) in self.children.iter_mut().zip(&widths) {
+            column.size.content = Rect {
+                x,
+                y: self.size.content.y,
+                w: width,
+                h: y - self.size.content.y,
             };
-        self.size.content.h = MIN_HEIGHT;
-        self.size.content.x = cursor.x + self.size.border.left;
-        self.size.content.y = cursor.y + self.size.border.top;
-        res = self.inline_children_loop(res, false);
-        cursor.y += self.size.content.h + self.size.border.top + self.size.border.bottom;
-        res
+            x += width + 1;
+        }
+        self.size.content.w = x - 1 - self.size.content.x;
+        self.size.content.h = y - self.size.content.y;
+        cursor.x = cursor.container.content.x;
+        cursor.y += self.size.content.h + self.size.inset().top + self.size.inset().bottom;
+        LayoutRes::Normal
+    }
+    /// Lay the box out, appending it to `laid` - with the rest of it after it
+    /// if it had to be cut
+    fn layout_pieces(mut self, cursor: &mut BoxCursor, laid: &mut Vec<DomBox<'a>>) {
+        loop {
+            match self.layout_generic(cursor) {
+                // a block has nowhere else to go
+                LayoutRes::Normal | LayoutRes::Full | LayoutRes::Reject => break,
+                LayoutRes::CutHere(next) => laid.push(std::mem::replace(&mut self, next)),
+            }
+        }
+        laid.push(self);
+    }
+    /// Lay this inline container out as as many lines as needed, appending them
+    /// to `lines`: each line takes its children from the front of what's left
+    fn layout_lines(mut self, cursor: &mut BoxCursor, lines: &mut Vec<DomBox<'a>>) {
+        let mut queue: VecDeque<DomBox<'a>> = std::mem::take(&mut self.children).into();
+        let width = if cursor.container.content.w > self.size.inset().left + self.size.inset().right
+        {
+            cursor.container.content.w - self.size.inset().left - self.size.inset().right
+        } else {
+            MIN_WIDTH
+        };
+        loop {
+            let mut line = DomBox {
+                kind: self.kind.clone(),
+                size: self.size,
+                style: self.style.clone(),
+                children: vec![],
+            };
+            line.size.content.w = width;
+            line.size.content.h = MIN_HEIGHT;
+            line.size.content.x = cursor.x + line.size.inset().left;
+            line.size.content.y = cursor.y + line.size.inset().top;
+            let mut subcursor = BoxCursor {
+                x: line.size.content.x,
+                y: line.size.content.y,
+                container: line.size,
+                widths: cursor.widths,
+                wrap: cursor.wrap,
+                collapse: true,
+            };
+            let mut more = false;
+            while let Some(mut child) = queue.pop_front() {
+                if let BoxKind::Break = child.kind {
+                    more = true;
+                    break;
+                }
+                // its children go on the lines by themselves, rather than it
+                // being cut on each line with all those left
+                if matches!(child.kind, BoxKind::Inline) && child.is_bare() {
+                    for grandchild in child.children.into_iter().rev() {
+                        queue.push_front(grandchild);
+                    }
+                    continue;
+                }
+                let mut res = child.layout_generic(&mut subcursor);
+                if let (LayoutRes::Reject, true) = (&res, line.children.is_empty()) {
+                    // it doesn't even fit on a line of its own: without its frame
+                    // its text can be cut to the line
+                    child.drop_sides();
+                    res = child.layout_generic(&mut subcursor);
+                }
+                match res {
+                    LayoutRes::Normal => line.children.push(child),
+                    LayoutRes::CutHere(next) => {
+                        line.children.push(child);
+                        queue.push_front(next);
+                        if self.style.wrap_marker && width > WRAP_MARKER_MIN_WIDTH {
+                            let mut style = self.style.clone();
+                            style.fg = DomColor::from_grey(0x80);
+                            queue.push_front(DomBox {
+                                size: Default::default(),
+                                kind: BoxKind::Text(CowStr::Borrowed("↪ ")),
+                                style,
+                                children: vec![],
+                            });
+                        }
+                        more = true;
+                        break;
+                    }
+                    LayoutRes::Full => {
+                        line.children.push(child);
+                        // drop everything up to the next line break, if any
+                        while queue
+                            .front()
+                            .is_some_and(|next| !matches!(next.kind, BoxKind::Break))
+                        {
+                            queue.pop_front();
+                        }
+                    }
+                    LayoutRes::Reject if line.children.is_empty() => {
+                        // still too wide, it goes over the end of the line
+                        line.children.push(child);
+                    }
+                    LayoutRes::Reject => {
+                        queue.push_front(child);
+                        more = true;
+                        break;
+                    }
+                }
+            }
+            line.size.content.w = subcursor.x - line.size.content.x;
+            cursor.y += line.height();
+            lines.push(line);
+            if !more {
+                break;
+            }
+        }
+    }
+    /// Drop the borders on the sides of an inline box and its children
+    fn drop_sides(&mut self) {
+        self.size.border.left = 0.into();
+        self.size.border.right = 0.into();
+        self.children.iter_mut().for_each(DomBox::drop_sides);
     }
     // this one can ask to be splitted if needs be, in this case the returned
     // element must be inserted right after the current one
@@ -560,183 +1789,457 @@ impl<'a> DomBox<'a> {
         self.size.content.h = MIN_HEIGHT;
         self.size.content.x = cursor.x + self.size.border.left;
         self.size.content.y = cursor.y + self.size.border.top;
-        self.size.content.w = cursor.container.content.w
-            - (cursor.x - cursor.container.content.x)
-            - (self.size.border.left + self.size.border.right);
+        // the cursor may be past the end of a container too narrow for its content
+        self.size.content.w = cursor
+            .container
+            .content
+            .w
+            .saturating_sub(cursor.x - cursor.container.content.x)
+            .saturating_sub(self.size.border.left + self.size.border.right);
         match self.kind {
+            BoxKind::Text(ref text) if !cursor.wrap => {
+                self.size.content.w = XY::saturating_from(cursor.widths.width(text));
+            }
             BoxKind::Text(ref mut text) => {
-                let width = UnicodeWidthStr::width(&text[..]).try_into().unwrap();
-                if self.size.content.w == 0.into() {
+                let widths = cursor.widths;
+                let cols = usize::from(self.size.content.w);
+                // only measure texts which may fit, they can be much longer than a line
+                let fits =
+                    findsplit(text, cols, widths) == text.len() && widths.width(text) <= cols;
+                let truncate = matches!(self.style.overflow, Overflow::Truncate);
+                if self.size.content.w == 0.into() && truncate {
+                    *text = CowStr::from("");
+                    res = LayoutRes::Full;
+                } else if self.size.content.w == 0.into() {
                     res = LayoutRes::Reject;
-                } else if width > self.size.content.w {
-                    let pos = findsplit(text, self.size.content.w.into());
+                } else if !fits && truncate {
+                    let pos = findsplit(text, usize::from(self.size.content.w) - 1, widths);
+                    split_at_in_place(text, pos);
+                    *text = format!("{}…", text).into();
+                    self.size.content.w = XY::saturating_from(widths.width(text));
+                    res = LayoutRes::Full;
+                } else if !fits {
+                    let (pos, hyphen) =
+                        findcut(text, self.size.content.w.into(), &self.style, widths);
+                    if pos == 0 && cursor.x > cursor.container.content.x {
+                        // a wide character doesn't fit in what's left of the line
+                        return LayoutRes::Reject;
+                    } else if pos == 0 {
+                        // nor in a whole line
+                        *text = CowStr::from("…");
+                        self.size.content.w = MIN_WIDTH;
+                        cursor.x += self.size.content.w;
+                        return LayoutRes::Full;
+                    }
                     let remains = split_at_in_place(text, pos);
+                    if hyphen {
+                        *text = format!("{}-", text).into();
+                    }
+                    let size = self.size;
+                    // the cut part may be narrower than the line, e.g. before a wide character
+                    self.size.content.w = XY::saturating_from(widths.width(text));
                     res = LayoutRes::CutHere(DomBox {
                         kind: BoxKind::Text(remains),
-                        size: self.size.clone(),
+                        size,
                         style: self.style.clone(),
                         children: vec![],
                     });
                 } else {
-                    self.size.content.w = width;
+                    self.size.content.w = XY::saturating_from(widths.width(text));
                 }
             }
-            BoxKind::Inline => {
-                res = self.inline_children_loop(res, true);
-            }
             _ => {
-                panic!("can't layout_inline {:?}", self.kind);
+                res = self.inline_children_loop(cursor);
             }
         };
         cursor.x += self.size.content.w;
         res
     }
-    pub fn render(&mut self) {
-        let mut strings = Vec::new();
-        for line in 0..(self.size.content.h + self.size.border.top + self.size.border.bottom).into()
-        {
-            self.render_line(line.try_into().unwrap(), &mut strings);
-            strings.push(Style::default().paint("\n"));
+    /// The first line of the laid out box, its border included
+    fn top(&self) -> XY {
+        self.size.content.y - self.size.inset().top
+    }
+    /// The line just below the laid out box
+    pub fn bottom(&self) -> XY {
+        self.size.content.y + self.size.content.h + self.size.inset().bottom
+    }
+    /// Total number of terminal lines this box occupies, borders included
+    pub fn height(&self) -> XY {
+        self.size.content.h + self.size.inset().top + self.size.inset().bottom
+    }
+    /// Render the box, optionally capping the output at `max_lines` lines - the
+    /// last allowed line then tells how many lines were left out
+    pub fn render<B: Backend>(&self, backend: &mut B, max_lines: Option<usize>) -> io::Result<()> {
+        self.render_tidy(backend, max_lines, Tidy::default(), WidthPolicy::default())
+    }
+    /// Render the box like `render`, its lines cleaned up as told by `tidy` - it
+    /// must have been laid out with these `widths`
+    pub fn render_tidy<B: Backend>(
+        &self,
+        backend: &mut B,
+        max_lines: Option<usize>,
+        tidy: Tidy,
+        widths: WidthPolicy,
+    ) -> io::Result<()> {
+        // no room even for the marker
+        if max_lines == Some(0) {
+            return Ok(());
+        }
+        // each line goes out as soon as it's rendered
+        let mut lines = self.tidy_lines(tidy, widths).peekable();
+        let mut shown = 0;
+        while let Some(spans) = lines.next() {
+            let capped = match max_lines {
+                Some(max) => shown >= max || (shown + 1 == max && lines.peek().is_some()),
+                None => false,
+            };
+            if capped {
+                let style = DomStyle {
+                    fg: DomColor::from_light(TermColor::Black),
+                    italic: true,
+                    ..Default::default()
+                };
+                let left = 1 + lines.count();
+                backend.write_styled(&style, &format!("… {} more lines", left))?;
+                break;
+            }
+            for span in spans {
+                backend.write_styled(&span.style, &span.text)?;
+            }
+            backend.end_line()?;
+            shown += 1;
+        }
+        backend.end_line()
+    }
+    /// Render the box as a piece of a longer output: like `render_tidy` uncapped,
+    /// and without the empty line ending it
+    pub fn render_piece<B: Backend>(
+        &self,
+        backend: &mut B,
+        tidy: Tidy,
+        widths: WidthPolicy,
+    ) -> io::Result<()> {
+        for spans in self.tidy_lines(tidy, widths) {
+            for span in spans {
+                backend.write_styled(&span.style, &span.text)?;
+            }
+            backend.end_line()?;
+        }
+        Ok(())
+    }
+    /// The styled pieces of our lines, one line at a time, cleaned up as told by `tidy`
+    fn tidy_lines(
+        &self,
+        tidy: Tidy,
+        widths: WidthPolicy,
+    ) -> impl Iterator<Item = Vec<StyledStr<'_>>> + '_ {
+        let mut blanks = 0;
+        (0..self.height().into()).filter_map(move |line| {
+            let mut spans = self.line_spans_with(line, widths);
+            if tidy.trim_trailing {
+                trim_trailing(&mut spans);
+            }
+            blanks = if spans.iter().all(StyledStr::is_blank) {
+                blanks + 1
+            } else {
+                0
+            };
+            if tidy.max_blank_lines.is_some_and(|max| blanks > max) {
+                return None;
+            }
+            Some(spans)
+        })
+    }
+    /// Render only the lines in `rows` - lines past the end of the box are ignored
+    pub fn render_rows<B: Backend>(
+        &self,
+        rows: Range<usize>,
+        backend: &mut B,
+        widths: WidthPolicy,
+    ) -> io::Result<()> {
+        let mut spans = vec![];
+        for line in rows.start..rows.end.min(self.height().into()) {
+            spans.clear();
+            if let Ok(line) = XY::try_from(line) {
+                self.render_line(line, &mut spans, widths);
+            }
+            for span in &spans {
+                backend.write_styled(&span.style, &span.text)?;
+            }
+            backend.end_line()?;
         }
-        println!("{}", ANSIStrings(&strings));
+        Ok(())
     }
-    fn render_line(&self, line: XY, strings: &mut Vec<ANSIString<'a>>) -> (XY, XY) {
-        if line < self.size.content.y - self.size.border.top
-            || line >= self.size.content.y + self.size.content.h + self.size.border.bottom
+    /// The styled pieces of text making up one line of the box, left to right
+    pub fn line_spans(&self, line: usize) -> Vec<StyledStr<'_>> {
+        self.line_spans_with(line, WidthPolicy::default())
+    }
+    /// Like `line_spans`, for a box laid out with these `widths`
+    pub fn line_spans_with(&self, line: usize, widths: WidthPolicy) -> Vec<StyledStr<'_>> {
+        let mut spans = Vec::new();
+        if let Ok(line) = XY::try_from(line) {
+            self.render_line(line, &mut spans, widths);
+        }
+        spans
+    }
+    fn render_line<'s>(
+        &'s self,
+        line: XY,
+        strings: &mut Vec<StyledStr<'s>>,
+        widths: WidthPolicy,
+    ) -> (XY, XY) {
+        let inset = self.size.inset();
+        let padding = self.size.padding;
+        if line < self.size.content.y - inset.top
+            || line >= self.size.content.y + self.size.content.h + inset.bottom
         {
             // out of the box, don't render anything
             return (0.into(), 0.into());
         }
-        if line < self.size.content.y || line >= self.size.content.y + self.size.content.h {
+        if line < self.size.content.y - padding.top
+            || line >= self.size.content.y + self.size.content.h + padding.bottom
+        {
             return self.render_borderline(line, strings);
         }
+        if let BoxKind::Table = self.kind {
+            return self.render_table_line(line, strings, widths);
+        }
         self.render_borderside(true, strings);
+        if padding.left > 0.into() {
+            self.render_spaces(padding.left, None, strings);
+        }
         let mut pos = self.size.content.x;
         match self.kind {
+            _ if line < self.size.content.y
+                || line >= self.size.content.y + self.size.content.h => {}
             BoxKind::Text(ref text) => {
-                let s = self.style.to_ansi().paint(text.to_string());
-                strings.push(s);
-                let incr: XY = UnicodeWidthStr::width(&text[..]).try_into().unwrap();
-                pos += incr;
-                assert!(pos <= self.size.content.x + self.size.content.w);
+                strings.push(StyledStr::new(&self.style, &**text));
+                // as measured by the layout
+                pos += self.size.content.w;
             }
             _ => {
-                for child in &self.children {
-                    let insert_point = strings.len().try_into().unwrap();
-                    let (start, len) = child.render_line(line, strings);
+                let end = self.size.content.x + self.size.content.w;
+                // stacked children: skip those above the line, stop below it
+                let stacked = matches!(
+                    self.kind,
+                    BoxKind::Block
+                        | BoxKind::Header(..)
+                        | BoxKind::ListBullet
+                        | BoxKind::List(_)
+                        | BoxKind::TableColumn
+                );
+                let skip = match self.kind {
+                    BoxKind::Block
+                    | BoxKind::Header(..)
+                    | BoxKind::ListBullet
+                    | BoxKind::TableColumn => self
+                        .children
+                        .partition_point(|child| child.bottom() <= line),
+                    // an item starts on the line of its bullet: of the children
+                    // starting above, only the last bullet and item may reach it
+                    BoxKind::List(_) => self
+                        .children
+                        .partition_point(|child| child.top() <= line)
+                        .saturating_sub(2),
+                    _ => 0,
+                };
+                for child in &self.children[skip..] {
+                    if stacked && child.top() > line {
+                        break;
+                    }
+                    let insert_point = strings.len();
+                    let (mut start, mut len) = child.render_line(line, strings, widths);
                     if len == 0.into() {
                         continue;
                     }
-                    assert!(start >= pos);
-                    assert!(start + len <= self.size.content.x + self.size.content.w);
+                    if start < pos || start + len > end {
+                        // a child too wide for us, e.g. at tiny widths: keep what fits
+                        let from = start.max(pos);
+                        let to = (start + len).min(end).max(from);
+                        clip_spans(
+                            &mut strings[insert_point..],
+                            from - start,
+                            to - from,
+                            widths,
+                        );
+                        (start, len) = (from, to - from);
+                    }
                     if start > pos {
-                        self.render_charline(' ', start - pos, Some(insert_point), strings);
+                        self.render_spaces(start - pos, Some(insert_point), strings);
                     }
                     pos = start + len;
                 }
-                assert!(pos <= self.size.content.x + self.size.content.w);
             }
         }
         if pos < self.size.content.x + self.size.content.w {
-            self.render_charline(
-                ' ',
+            self.render_spaces(
                 self.size.content.x + self.size.content.w - pos,
                 None,
                 strings,
             );
         }
+        if padding.right > 0.into() {
+            self.render_spaces(padding.right, None, strings);
+        }
         self.render_borderside(false, strings);
-        return (
-            self.size.content.x - self.size.border.left,
-            self.size.content.w + self.size.border.left + self.size.border.right,
-        );
+        (
+            self.size.content.x - inset.left,
+            self.size.content.w + inset.left + inset.right,
+        )
     }
-    fn render_borderline(&self, line: XY, strings: &mut Vec<ANSIString<'a>>) -> (XY, XY) {
+    fn render_borderline<'s>(&'s self, line: XY, strings: &mut Vec<StyledStr<'s>>) -> (XY, XY) {
         let is_top = line < self.size.content.y;
-        let mut s = String::with_capacity(
-            ((self.size.content.w + self.size.border.left + self.size.border.right) * 4).into(),
-        );
+        let inset = self.size.inset();
+        let padded = self.size.content.w + self.size.padding.left + self.size.padding.right;
+        let mut s = String::with_capacity(((padded + inset.left + inset.right) * 4).into());
+        let chars = self.style.border_type.chars();
+        let (left, right) = if is_top {
+            (chars.top_left, chars.top_right)
+        } else {
+            (chars.bottom_left, chars.bottom_right)
+        };
         for _ in 0..self.size.border.left.into() {
-            match self.style.border_type {
-                _ => {
-                    s.push(if is_top { '┌' } else { '└' });
-                }
-            }
+            s.push(left);
         }
-        for _ in 0..self.size.content.w.into() {
-            match self.style.border_type {
-                BorderType::Empty => {
-                    s.push(' ');
-                }
-                BorderType::Dash => {
-                    s.push('╌');
-                }
-                BorderType::Thin => {
-                    s.push('─');
-                }
-                BorderType::Double => {
-                    s.push('═');
+        match self.kind {
+            // a tee where each rule between the columns meets the border
+            BoxKind::Table => {
+                let tee = if is_top {
+                    chars.top_tee
+                } else {
+                    chars.bottom_tee
+                };
+                for (i, column) in self.children.iter().enumerate() {
+                    if i > 0 {
+                        s.push(tee);
+                    }
+                    for _ in 0..column.size.content.w.into() {
+                        s.push(chars.horizontal);
+                    }
                 }
-                BorderType::Bold => {
-                    s.push('━');
+            }
+            _ => {
+                for _ in 0..padded.into() {
+                    s.push(chars.horizontal);
                 }
             }
         }
         for _ in 0..self.size.border.right.into() {
-            s.push(if is_top { '┐' } else { '┘' });
+            s.push(right);
+        }
+        strings.push(StyledStr {
+            style: Cow::Owned(self.style.border()),
+            text: s.into(),
+        });
+        (
+            self.size.content.x - inset.left,
+            self.size.content.w + inset.left + inset.right,
+        )
+    }
+    /// A line of a table inside its top and bottom borders: the cells, the
+    /// rules between them, or the rule under the headings
+    fn render_table_line<'s>(
+        &'s self,
+        line: XY,
+        strings: &mut Vec<StyledStr<'s>>,
+        widths: WidthPolicy,
+    ) -> (XY, XY) {
+        let chars = self.style.border_type.chars();
+        let headings = self.children[0].children.first();
+        let under_headings = self.children[0].children.len() > 1
+            && headings.is_some_and(|headings| headings.bottom() == line);
+        let (left, right, cross) = match under_headings {
+            true => (chars.left_tee, chars.right_tee, chars.cross),
+            false => (chars.vertical, chars.vertical, chars.vertical),
+        };
+        let rule = |c: char, n: XY| StyledStr {
+            style: Cow::Owned(self.style.border()),
+            text: c.to_string().repeat(n.into()).into(),
+        };
+        strings.push(rule(left, self.size.border.left));
+        for (i, column) in self.children.iter().enumerate() {
+            if i > 0 {
+                strings.push(rule(cross, 1.into()));
+            }
+            if under_headings {
+                strings.push(rule(chars.horizontal, column.size.content.w));
+            } else if column.render_line(line, strings, widths).1 == 0.into() {
+                column.render_spaces(column.size.content.w, None, strings);
+            }
         }
-        let s = self.style.to_ansi().paint(s);
-        strings.push(s);
-        return (
-            self.size.content.x - self.size.border.left,
-            self.size.content.w + self.size.border.left + self.size.border.right,
-        );
+        strings.push(rule(right, self.size.border.right));
+        let inset = self.size.inset();
+        (
+            self.size.content.x - inset.left,
+            self.size.content.w + inset.left + inset.right,
+        )
     }
-    fn render_borderside(&self, is_left: bool, strings: &mut Vec<ANSIString<'a>>) {
+    fn render_borderside<'s>(&'s self, is_left: bool, strings: &mut Vec<StyledStr<'s>>) {
         let width = if is_left {
             self.size.border.left
         } else {
             self.size.border.right
         };
         let mut s = String::with_capacity((width * 4).into());
+        let side = self.style.border_type.chars().vertical;
         for _ in 0..width.into() {
-            match self.style.border_type {
-                BorderType::Empty => {
-                    s.push(' ');
-                }
-                BorderType::Dash => {
-                    s.push('╎');
-                }
-                BorderType::Thin => {
-                    s.push('│');
-                }
-                BorderType::Double => {
-                    s.push('║');
-                }
-                BorderType::Bold => {
-                    s.push('┃');
-                }
-            }
+            s.push(side);
         }
-        let s = self.style.to_ansi().paint(s);
-        strings.push(s);
+        strings.push(StyledStr {
+            style: Cow::Owned(self.style.border()),
+            text: s.into(),
+        });
     }
-    fn render_charline(
-        &self,
-        c: char,
-        n: XY,
-        insert: Option<XY>,
-        strings: &mut Vec<ANSIString<'a>>,
-    ) {
-        let mut s = String::with_capacity((n * 4).into());
-        for _ in 0..n.into() {
-            s.push(c);
-        }
-        let s = self.style.to_ansi().paint(s);
+    /// Blank columns of ours, around or between our children: in our background,
+    /// inherited if we have none, so that it's the nearest one painted around a child
+    /// with its own - like an inline code in a tinted quote
+    fn render_spaces<'s>(&'s self, n: XY, insert: Option<usize>, strings: &mut Vec<StyledStr<'s>>) {
+        let n = usize::from(n);
+        let s = match SPACES.get(..n) {
+            Some(spaces) => StyledStr::new(&self.style, spaces),
+            None => StyledStr::new(&self.style, " ".repeat(n)),
+        };
         if let Some(insert) = insert {
-            strings.insert(insert.into(), s);
+            strings.insert(insert, s);
         } else {
             strings.push(s);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of the laid out lines, styles left out
+    fn lines(root: &DomBox) -> Vec<String> {
+        (0..usize::from(root.height()))
+            .map(|line| root.line_spans(line).iter().map(|s| &*s.text).collect())
+            .collect()
+    }
+
+    #[test]
+    fn framed_text_wider_than_the_line_is_kept() {
+        let mut root = DomBox::new_root(1.into());
+        let text = root.add_text(CowStr::Borrowed("abc"));
+        text.size.border.left = 1.into();
+        text.size.border.right = 1.into();
+        root.layout();
+        assert_eq!(lines(&root).concat().trim(), "abc");
+    }
+
+    #[test]
+    fn clipped_as_laid_out() {
+        let style = DomStyle::default();
+        let wide = WidthPolicy {
+            ambiguous_wide: true,
+            ..Default::default()
+        };
+        let mut spans = [StyledStr::new(&style, "①②")];
+        clip_spans(&mut spans, 0.into(), 3.into(), wide);
+        assert_eq!(spans[0].text, "① ");
+        let mut spans = [StyledStr::new(&style, "①②")];
+        clip_spans(&mut spans, 0.into(), 1.into(), WidthPolicy::default());
+        assert_eq!(spans[0].text, "①");
+    }
+}
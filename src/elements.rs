@@ -0,0 +1,23 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Hooks into the building of the boxes of some markdown elements
+
+use crate::dombox::DomBox;
+
+/// Callbacks getting the boxes of each element as built by catmark, to change them
+/// or replace their content - e.g. to add a badge after a heading's text, or
+/// show a placeholder instead of an image - they do nothing by default
+pub trait ElementRenderer {
+    /// A heading, from level 1, with its id if it has one
+    fn on_heading(&self, _heading: &mut DomBox<'_>, _level: u8, _anchor: Option<&str>) {}
+    /// A block quote, or a GitHub alert
+    fn on_blockquote(&self, _quote: &mut DomBox<'_>) {}
+    /// A code block, with its lowercase language if it's fenced with one, or ""
+    fn on_code_block(&self, _block: &mut DomBox<'_>, _lang: &str) {}
+    /// A link, with where it leads as written in the markdown
+    fn on_link(&self, _link: &mut DomBox<'_>, _dest: &str) {}
+    /// An image, made of its title, its resolved destination and its alternative text
+    fn on_image(&self, _image: &mut DomBox<'_>, _dest: &str, _title: &str) {}
+}
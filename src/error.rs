@@ -0,0 +1,102 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! What can go wrong, for the `Result`-returning functions
+
+use crate::warnings::Warning;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+#[cfg(feature = "syntax-highlighting")]
+use syntect::LoadingError;
+
+/// Why catmark couldn't do what it was asked
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CatmarkError {
+    /// Reading the input or writing the output failed
+    Io(io::Error),
+    /// The margins leave no room for the content
+    WidthTooSmall { width: u16, min: u16 },
+    /// No heading looks like the section to render, `headings` being where the
+    /// headings looked through are, in bytes from the start of the markdown
+    SectionNotFound {
+        name: String,
+        headings: Vec<Range<usize>>,
+    },
+    /// Something isn't rendered as it should, when that can't be let go
+    Warning(Warning),
+    /// A line of a configuration file isn't like `name = value`, `line` counting
+    /// from 1
+    Config { line: usize, text: String },
+    /// A line of a palette file isn't a `#rrggbb` color, or there are more than 256
+    Palette { line: usize, text: String },
+    /// Syntax definitions or themes couldn't be loaded
+    #[cfg(feature = "syntax-highlighting")]
+    Loading(LoadingError),
+}
+
+pub type Result<T> = std::result::Result<T, CatmarkError>;
+
+impl fmt::Display for CatmarkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatmarkError::Io(err) => write!(f, "{}", err),
+            CatmarkError::WidthTooSmall { width, min } => {
+                write!(
+                    f,
+                    "{} columns are too few, at least {} are needed",
+                    width, min
+                )
+            }
+            CatmarkError::SectionNotFound { name, .. } => {
+                write!(f, "no section matching \"{}\"", name)
+            }
+            CatmarkError::Warning(warning) => write!(f, "{}", warning.kind),
+            CatmarkError::Config { line, text } => {
+                write!(
+                    f,
+                    "configuration line {} isn't like name = value: {}",
+                    line, text
+                )
+            }
+            CatmarkError::Palette { line, text } => {
+                write!(f, "palette line {} isn't a #rrggbb color: {}", line, text)
+            }
+            #[cfg(feature = "syntax-highlighting")]
+            CatmarkError::Loading(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for CatmarkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CatmarkError::Io(err) => Some(err),
+            #[cfg(feature = "syntax-highlighting")]
+            CatmarkError::Loading(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CatmarkError {
+    fn from(err: io::Error) -> Self {
+        CatmarkError::Io(err)
+    }
+}
+
+impl From<Warning> for CatmarkError {
+    fn from(warning: Warning) -> Self {
+        CatmarkError::Warning(warning)
+    }
+}
+
+#[cfg(feature = "syntax-highlighting")]
+impl From<LoadingError> for CatmarkError {
+    fn from(err: LoadingError) -> Self {
+        CatmarkError::Loading(err)
+    }
+}
@@ -0,0 +1,1289 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Markdown (CommonMark) ANSI renderer.
+
+mod ansi_renderer;
+pub mod backend;
+pub mod dombox;
+mod elements;
+pub mod error;
+mod math;
+mod roff;
+mod stats;
+mod stylesheet;
+#[cfg(feature = "terminal")]
+pub mod terminal;
+mod trace;
+#[cfg(feature = "ratatui")]
+mod tui;
+mod warnings;
+mod xy;
+
+pub use crate::backend::Cell;
+pub use crate::dombox::{DomBox, Palette, StyledStr, Tidy, WidthPolicy};
+pub use crate::elements::ElementRenderer;
+pub use crate::error::CatmarkError;
+pub use crate::stats::Stats;
+pub use crate::stylesheet::{HeadingStyle, Stylesheet};
+#[cfg(feature = "ratatui")]
+pub use crate::tui::{MarkdownState, MarkdownView};
+pub use crate::warnings::{Warning, WarningKind};
+pub use crate::xy::XY;
+pub use pulldown_cmark;
+#[cfg(feature = "search")]
+pub use regex;
+#[cfg(feature = "syntax-highlighting")]
+pub use syntect;
+
+use crate::backend::{AnsiTerm, Backend, Grid, Html, PlainText, Svg};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+#[cfg(not(feature = "syntax-highlighting"))]
+use std::marker::PhantomData;
+use std::ops::Range;
+#[cfg(feature = "syntax-highlighting")]
+use std::path::Path;
+#[cfg(feature = "syntax-highlighting")]
+use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
+
+pub const DEFAULT_COLS: u16 = 80;
+
+/// What a markdown event becomes before the boxes are built from it - `None` drops
+/// it, e.g. to strip HTML or rewrite image URLs - the start and end of a tag had
+/// better be both kept or both dropped, a lone one is dropped or closed anyway
+pub type EventFilter<'f> = dyn for<'e> Fn(Event<'e>) -> Option<Event<'e>> + 'f;
+
+/// Informal code fence languages, and what syntect knows them as
+const LANG_ALIASES: &[(&str, &str)] = &[
+    ("shell", "sh"),
+    ("console", "sh"),
+    ("shell-session", "sh"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("dockerfile", "Dockerfile"),
+    ("docker", "Dockerfile"),
+    ("golang", "go"),
+    ("csharp", "cs"),
+    ("c#", "cs"),
+    ("objc", "m"),
+    ("objective-c", "m"),
+    ("jsonc", "json"),
+    ("json5", "json"),
+    ("latex", "tex"),
+    ("text", "txt"),
+    ("plaintext", "txt"),
+    ("plain", "txt"),
+];
+
+/// Whether the terminal has a dark or a light background
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+/// Styles and code theme going together
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Preset {
+    /// For terminals with a dark background, the default
+    Dark,
+    /// For terminals with a light background
+    Light,
+    /// Without any color, for monochrome terminals or pipes keeping the layout
+    Plain,
+    /// Bright and bold colors, for a dark background
+    HighContrast,
+}
+
+impl Preset {
+    /// The terminal background it's made for, if it has colors
+    pub fn background(self) -> Option<Background> {
+        match self {
+            Preset::Dark | Preset::HighContrast => Some(Background::Dark),
+            Preset::Light => Some(Background::Light),
+            Preset::Plain => None,
+        }
+    }
+}
+
+/// How links show their destination
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LinkStyle {
+    /// After the text, like "text (url)"
+    Inline,
+    /// With a number after the text, like "text[3]", the URLs being listed at the end
+    #[default]
+    Reference,
+    /// As terminal hyperlinks (OSC 8) only
+    Osc8,
+    /// Not at all
+    Hidden,
+}
+
+/// How blockquotes are set apart
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum QuoteStyle {
+    /// With a border on their left
+    #[default]
+    Border,
+    /// With "> " starting each of their lines, which copies better as plain text
+    Prefix,
+}
+
+/// How horizontal rules are drawn, in the `rule` style of the stylesheet
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RuleStyle {
+    /// A line across the whole width
+    #[default]
+    Full,
+    /// A line across this percentage of the width, centered
+    Centered(u16),
+    /// A line across this percentage of the width, from the left
+    Partial(u16),
+    /// This text centered instead of a line, like "❦"
+    Ornament(String),
+}
+
+/// Blank lines after each kind of block
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Spacing {
+    pub paragraph: u16,
+    pub heading: u16,
+    pub code_block: u16,
+    pub list: u16,
+    pub quote: u16,
+    pub table: u16,
+}
+
+impl Default for Spacing {
+    fn default() -> Self {
+        Spacing {
+            paragraph: 1,
+            heading: 0,
+            code_block: 1,
+            list: 1,
+            quote: 1,
+            table: 1,
+        }
+    }
+}
+
+impl Spacing {
+    /// No blank lines at all, for preview panes
+    pub fn compact() -> Self {
+        Spacing {
+            paragraph: 0,
+            heading: 0,
+            code_block: 0,
+            list: 0,
+            quote: 0,
+            table: 0,
+        }
+    }
+}
+
+/// Room left blank on the sides of the document
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Margins {
+    pub left: u16,
+    pub right: u16,
+    /// Past this width, the content is centered with wider margins
+    pub max_width: Option<u16>,
+}
+
+impl Margins {
+    /// The left and right margins for a document `width` columns wide
+    pub fn edges(&self, width: u16) -> (u16, u16) {
+        let content = width.saturating_sub(self.left.saturating_add(self.right));
+        let extra = match self.max_width {
+            Some(max) if content > max => content - max,
+            _ => 0,
+        };
+        (self.left + extra / 2, self.right + extra - extra / 2)
+    }
+}
+
+/// Everything that can be tuned when rendering a document - nothing else is: the
+/// same text with the same options renders the same bytes on any platform, in
+/// any terminal
+pub struct RenderOptions<'s> {
+    /// Output width, in terminal columns
+    pub width: u16,
+    /// Wrap the lines longer than the width - without wrapping they're as long as
+    /// they need, e.g. for tools folding them on their own
+    pub wrap: bool,
+    /// Styles of the markdown elements
+    pub stylesheet: Stylesheet,
+    /// Colors of the terminal, to show the highlighted code in the closest ones
+    pub palette: Palette,
+    /// Syntax definitions for code blocks, instead of syntect's default ones - they
+    /// must have been loaded for lines including their newline
+    #[cfg(feature = "syntax-highlighting")]
+    pub syntaxes: Option<&'s SyntaxSet>,
+    /// Themes for code blocks, instead of syntect's default ones
+    #[cfg(feature = "syntax-highlighting")]
+    pub themes: Option<&'s ThemeSet>,
+    /// Name of the code blocks theme - if it's not in the theme set any theme is used
+    #[cfg(feature = "syntax-highlighting")]
+    pub theme: &'s str,
+    /// Highlight the syntax of code blocks - without it they're in the style of
+    /// the stylesheet only
+    #[cfg(feature = "syntax-highlighting")]
+    pub code_colors: bool,
+    /// Code fence languages to look up as another one, keys are lowercase
+    pub lang_aliases: HashMap<String, String>,
+    /// Guess the language of indented code blocks from their first line (a shebang,
+    /// `<?php`, ...) to highlight them
+    pub detect_lang: bool,
+    /// End each footnote with the titles of the sections referencing it
+    pub footnote_backlinks: bool,
+    /// Show the footnotes with only inline content in brackets right after their
+    /// references, instead of at the end
+    pub inline_footnotes: bool,
+    /// Where link destinations are shown
+    pub link_style: LinkStyle,
+    /// Leave intra-document `#anchor` links out of the links list
+    pub skip_anchor_links: bool,
+    /// Leave `mailto:` links out of the links list
+    pub skip_mailto_links: bool,
+    /// Directory path or URL relative links are resolved against
+    pub link_base: Option<String>,
+    /// Parse tables
+    pub tables: bool,
+    /// Parse footnotes
+    pub footnotes: bool,
+    /// Parse `~~strikethrough~~`
+    pub strikethrough: bool,
+    /// Parse `[ ]` and `[x]` task list items
+    pub tasklists: bool,
+    /// Turn quotes, `--` and `...` into their typographic equivalents
+    pub smart_punctuation: bool,
+    /// Parse `{#id .class}` attributes after headings
+    pub heading_attributes: bool,
+    /// Show `$math$` and `$$math$$` with Unicode approximations
+    pub math: bool,
+    /// Highlight `==marked text==` like Obsidian or markdown-it do
+    pub highlight: bool,
+    /// Highlight the matches of this pattern in the rendered text
+    #[cfg(feature = "search")]
+    pub search: Option<regex::Regex>,
+    /// Render only the top-level blocks with a match of this pattern, after the
+    /// heading of their section for context
+    #[cfg(feature = "search")]
+    pub grep: Option<regex::Regex>,
+    /// Markers of the unchecked and checked task list items, like "☐" and "☑"
+    pub task_markers: (String, String),
+    /// Dim and strike through the checked task list items
+    pub strike_done_tasks: bool,
+    /// Bullets of the unordered list items, by nesting level - deeper ones reuse
+    /// them in turn
+    pub bullets: Vec<String>,
+    /// Cut words at their syllables when they must be cut, if built with the
+    /// `hyphenate` feature
+    pub hyphenate: bool,
+    /// How many columns characters take in the terminal
+    pub widths: WidthPolicy,
+    /// Cleanups of the output lines
+    pub tidy: Tidy,
+    /// Blank columns around the document
+    pub margins: Margins,
+    /// Blank lines between blocks
+    pub spacing: Spacing,
+    /// Width of horizontal rules, or an ornament instead
+    pub rule_style: RuleStyle,
+    /// Border or prefix for blockquotes
+    pub quote_style: QuoteStyle,
+    /// Turn `:rocket:`-like shortcodes into emoji
+    pub emoji: bool,
+    /// Show the ids of headings after their text, to know how to link to them
+    pub show_heading_ids: bool,
+    /// Prefix headings with their section number, like "1.2.3"
+    pub number_headings: bool,
+    /// Frame the blocks with borders colored by their depth, to debug the layout
+    pub outline_boxes: bool,
+    /// Render only the section with this title (or the closest one), and its
+    /// subsections
+    pub section: Option<String>,
+    /// Filters the parsed events go through in turn - they're called once per event,
+    /// in order, so one needing to know where it is (e.g. in which section) can keep
+    /// track of it in a `Cell`
+    pub filters: Vec<&'s EventFilter<'s>>,
+    /// Changes to the boxes built for some elements
+    pub elements: Option<&'s dyn ElementRenderer>,
+    #[cfg(not(feature = "syntax-highlighting"))]
+    _marker: PhantomData<&'s ()>,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        RenderOptions {
+            width: DEFAULT_COLS,
+            wrap: true,
+            stylesheet: Default::default(),
+            palette: Palette::default(),
+            #[cfg(feature = "syntax-highlighting")]
+            syntaxes: None,
+            #[cfg(feature = "syntax-highlighting")]
+            themes: None,
+            #[cfg(feature = "syntax-highlighting")]
+            theme: "base16-eighties.dark",
+            #[cfg(feature = "syntax-highlighting")]
+            code_colors: true,
+            lang_aliases: LANG_ALIASES
+                .iter()
+                .map(|&(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            detect_lang: true,
+            footnote_backlinks: true,
+            inline_footnotes: false,
+            link_style: LinkStyle::default(),
+            skip_anchor_links: false,
+            skip_mailto_links: false,
+            link_base: None,
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            tasklists: true,
+            smart_punctuation: true,
+            heading_attributes: true,
+            math: false,
+            highlight: false,
+            #[cfg(feature = "search")]
+            search: None,
+            #[cfg(feature = "search")]
+            grep: None,
+            task_markers: ("☐".to_string(), "☑".to_string()),
+            strike_done_tasks: false,
+            quote_style: QuoteStyle::default(),
+            rule_style: RuleStyle::default(),
+            spacing: Spacing::default(),
+            margins: Margins::default(),
+            hyphenate: false,
+            widths: WidthPolicy::default(),
+            tidy: Tidy::default(),
+            bullets: ["•", "◦", "▪"].map(String::from).to_vec(),
+            emoji: true,
+            show_heading_ids: false,
+            number_headings: false,
+            outline_boxes: false,
+            section: None,
+            filters: vec![],
+            elements: None,
+            #[cfg(not(feature = "syntax-highlighting"))]
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl RenderOptions<'_> {
+    /// Default options for the given width
+    pub fn with_width(width: u16) -> Self {
+        RenderOptions {
+            width,
+            ..Default::default()
+        }
+    }
+    /// The markdown extensions to parse
+    pub fn parser_options(&self) -> Options {
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.tasklists);
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options.set(Options::ENABLE_HEADING_ATTRIBUTES, self.heading_attributes);
+        options
+    }
+    /// Switch the styles and the code theme to ones suited to this background
+    pub fn set_background(&mut self, background: Background) {
+        self.set_preset(match background {
+            Background::Dark => Preset::Dark,
+            Background::Light => Preset::Light,
+        });
+    }
+    /// Switch the styles and the code theme to the ones of a preset
+    pub fn set_preset(&mut self, preset: Preset) {
+        self.stylesheet = match preset {
+            Preset::Dark => Stylesheet::default(),
+            Preset::Light => Stylesheet::light(),
+            Preset::Plain => Stylesheet::plain(),
+            Preset::HighContrast => Stylesheet::high_contrast(),
+        };
+        #[cfg(feature = "syntax-highlighting")]
+        {
+            self.theme = match preset {
+                Preset::Light => "InspiredGitHub",
+                _ => "base16-eighties.dark",
+            };
+            self.code_colors = preset != Preset::Plain;
+        }
+    }
+}
+
+/// syntect's default syntaxes plus all the `.sublime-syntax` files found in `dirs`,
+/// ready to be used as `RenderOptions::syntaxes`
+#[cfg(feature = "syntax-highlighting")]
+pub fn load_syntaxes<P: AsRef<Path>>(dirs: &[P]) -> Result<SyntaxSet, CatmarkError> {
+    let mut syntaxes = SyntaxSet::load_defaults_newlines();
+    for dir in dirs {
+        syntaxes.load_syntaxes(dir, true)?;
+    }
+    syntaxes.link_syntaxes();
+    Ok(syntaxes)
+}
+
+/// Number of terminal lines `text` will occupy once rendered `width` columns wide,
+/// without actually rendering it
+pub fn measure(text: &str, width: u16) -> usize {
+    let opts = RenderOptions::with_width(width);
+    let p = Parser::new_ext(text, opts.parser_options());
+    ansi_renderer::build_dom(p, &opts).measure()
+}
+
+/// Length of the start of `text` made of top-level blocks that can't change
+/// anymore, whatever gets appended: all of them but the last one - this is
+/// what a stream can already render
+pub fn complete_blocks(text: &str, opts: &RenderOptions) -> usize {
+    let mut depth = 0;
+    let mut last = 0;
+    for (event, range) in Parser::new_ext(text, opts.parser_options()).into_offset_iter() {
+        match event {
+            Event::Start(_) => {
+                if depth == 0 {
+                    last = range.start;
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth -= 1,
+            _ if depth == 0 => last = range.start,
+            _ => {}
+        }
+    }
+    // with the indentation of its first line
+    text[..last].rfind('\n').map_or(0, |end| end + 1)
+}
+
+/// The options of a configuration file, as command line arguments: each
+/// `name = value` line becomes `--name value`, and a lone `name` becomes `--name` -
+/// empty lines and those starting with "#" are skipped
+pub fn parse_config(config: &str) -> Result<Vec<String>, CatmarkError> {
+    let mut args = vec![];
+    for (n, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(CatmarkError::Config {
+                line: n + 1,
+                text: line.to_string(),
+            });
+        }
+        args.push(format!("--{}", name));
+        args.extend(value.map(String::from));
+    }
+    Ok(args)
+}
+
+/// The colors of a terminal from a list of `#rrggbb`, one per line from the color 0 -
+/// usually the 16 base colors, the others being the same in most terminals
+pub fn parse_palette(text: &str) -> Result<Palette, CatmarkError> {
+    let mut palette = Palette::default();
+    let mut colors = 0usize;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line.strip_prefix('#').unwrap_or(line);
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        match (u8::try_from(colors), channel(0), channel(2), channel(4)) {
+            (Ok(index), Some(red), Some(green), Some(blue)) if hex.len() == 6 => {
+                palette.set(index, [red, green, blue]);
+                colors += 1;
+            }
+            _ => {
+                return Err(CatmarkError::Palette {
+                    line: n + 1,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+    Ok(palette)
+}
+
+/// Lay out markdown events as parsed by pulldown-cmark - by the caller's own parser or
+/// made up - without going through markdown text, ready to render as a `Document`.
+/// Badly nested tags are fixed: an end without its start is dropped, the tags left
+/// open are closed.
+pub fn render_events<'a, I: Iterator<Item = Event<'a>>>(
+    iter: I,
+    opts: &RenderOptions,
+) -> Document<'a> {
+    Document::from_events(iter, opts)
+}
+
+/// `text` without its byte order mark and with Unix line endings, as written by
+/// some Windows editors - a carriage return left in a line would show up
+pub fn clean_input(text: &str) -> Cow<'_, str> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// A markdown document, laid out for a given terminal width
+pub struct Document<'a> {
+    /// The DOM as built, before layout splits it into lines
+    source: DomBox<'a>,
+    root: DomBox<'a>,
+    width: u16,
+    wrap: bool,
+    margins: Margins,
+    widths: WidthPolicy,
+    tidy: Tidy,
+    palette: Palette,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Document<'a> {
+    /// Parse `text` and lay it out `width` columns wide
+    pub fn new(text: &'a str, width: u16) -> Self {
+        Document::with_options(text, &RenderOptions::with_width(width))
+    }
+    /// Parse `text` and lay it out according to `opts`
+    pub fn with_options(text: &'a str, opts: &RenderOptions) -> Self {
+        let text = clean_input(text);
+        let mut doc = match text {
+            Cow::Borrowed(text) => {
+                Document::from_events(Parser::new_ext(text, opts.parser_options()), opts)
+            }
+            // the boxes can't borrow from the cleaned up copy, they get their texts
+            Cow::Owned(ref text) => {
+                let p = Parser::new_ext(text, opts.parser_options());
+                Document::from_source(ansi_renderer::build_dom(p, opts).into_owned(), opts)
+            }
+        };
+        doc.warnings = warnings::check(&text, opts);
+        doc
+    }
+    /// Lay out already parsed markdown, without its warnings - there's no text to
+    /// find them in
+    pub fn from_events<I: Iterator<Item = Event<'a>>>(iter: I, opts: &RenderOptions) -> Self {
+        Document::from_source(ansi_renderer::build_dom(iter, opts), opts)
+    }
+    /// Lay out the boxes built for a document
+    fn from_source(source: DomBox<'a>, opts: &RenderOptions) -> Self {
+        let mut doc = Document {
+            source,
+            root: DomBox::new_block(),
+            width: opts.width,
+            wrap: opts.wrap,
+            margins: opts.margins,
+            widths: opts.widths,
+            tidy: opts.tidy,
+            palette: opts.palette.clone(),
+            warnings: vec![],
+        };
+        doc.relayout(opts.width);
+        doc
+    }
+    /// Like `with_options`, but failing when the document can't be rendered as
+    /// asked: with no room for the content between the margins, or without the
+    /// section to render
+    pub fn try_with_options(text: &'a str, opts: &RenderOptions) -> Result<Self, CatmarkError> {
+        let min = opts
+            .margins
+            .left
+            .saturating_add(opts.margins.right)
+            .saturating_add(1);
+        if opts.wrap && opts.width < min {
+            return Err(CatmarkError::WidthTooSmall {
+                width: opts.width,
+                min,
+            });
+        }
+        let doc = Document::with_options(text, opts);
+        match opts.section {
+            Some(ref name) if doc.height() == 0 => {
+                let text = clean_input(text);
+                let parser = Parser::new_ext(&text, opts.parser_options()).into_offset_iter();
+                let headings = parser.filter_map(|(event, range)| match event {
+                    Event::Start(Tag::Heading(..)) => Some(range),
+                    _ => None,
+                });
+                Err(CatmarkError::SectionNotFound {
+                    name: name.clone(),
+                    headings: headings.collect(),
+                })
+            }
+            _ => Ok(doc),
+        }
+    }
+    /// Lay the document out again for a new width, e.g. after a terminal resize -
+    /// the markdown isn't parsed nor highlighted again
+    pub fn relayout(&mut self, width: u16) {
+        self.width = width;
+        let mut root = self.source.clone();
+        self.fit(&mut root);
+        self.lay_out(&mut root, 0);
+        self.root = root;
+    }
+    /// Give a root box our width and margins
+    fn fit(&self, root: &mut DomBox) {
+        if !self.wrap {
+            // the width is that of the longest line
+            root.set_margins(self.margins.left.into(), self.margins.right.into());
+            return;
+        }
+        root.set_width(self.width.into());
+        let (left, right) = self.margins.edges(self.width);
+        root.set_margins(left.into(), right.into());
+    }
+    /// Lay a fitted root box out, its first `from` children being already laid
+    /// out as in `layout_from`
+    fn lay_out(&self, root: &mut DomBox, from: usize) {
+        let _span = trace::span!("layout");
+        if self.wrap {
+            root.layout_from(from, self.widths);
+        } else {
+            root.layout_unwrapped(self.widths);
+        }
+    }
+    /// Parse a new version of the text, laying it out again from its first
+    /// top-level block that changed - returns the lines which look different now,
+    /// for a view to redraw only them with `render_updates`
+    pub fn update(&mut self, text: &str, opts: &RenderOptions) -> Vec<usize> {
+        let text = clean_input(text);
+        let p = Parser::new_ext(&text, opts.parser_options());
+        let source = ansi_renderer::build_dom(p, opts).into_owned();
+        // the layout may split boxes, then the laid out ones don't match the others
+        // without wrapping, any change may change the width of the whole
+        let same = if self.wrap
+            && self.root.children.len() == self.source.children.len()
+            && source.size == self.source.size
+            && source.style == self.source.style
+        {
+            let pairs = source.children.iter().zip(&self.source.children);
+            pairs.take_while(|(new, old)| new == old).count()
+        } else {
+            0
+        };
+        let first_line = match same {
+            0 => 0,
+            n => self.root.children[..n]
+                .iter()
+                .map(|child| usize::from(child.bottom()))
+                .max()
+                .unwrap_or(0),
+        };
+        let mut root = source.clone();
+        self.fit(&mut root);
+        root.children[..same].clone_from_slice(&self.root.children[..same]);
+        self.lay_out(&mut root, same);
+        let end = self.height().max(root.height().into());
+        let changed = (first_line..end)
+            .filter(|&line| {
+                self.root.line_spans_with(line, self.widths)
+                    != root.line_spans_with(line, self.widths)
+            })
+            .collect();
+        self.source = source;
+        self.root = root;
+        self.warnings = warnings::check(&text, opts);
+        changed
+    }
+    /// What redraws these lines of the document in place on a terminal, its first
+    /// line being at the row `top` (from 0) - the lines past its end are cleared
+    pub fn render_updates(&self, lines: &[usize], top: usize) -> String {
+        let mut out = String::new();
+        for &line in lines {
+            out.push_str(&format!("\x1b[{};1H", top + line + 1));
+            out.push_str(self.render_rows(line..line + 1).trim_end_matches('\n'));
+            out.push_str("\x1b[K");
+        }
+        out
+    }
+    /// Number of terminal lines of the whole rendered document
+    pub fn height(&self) -> usize {
+        self.root.height().into()
+    }
+    /// Render only some lines of the document, e.g. the visible part of a scrolled view
+    pub fn render_rows(&self, rows: Range<usize>) -> String {
+        let _span = trace::span!("render");
+        let mut backend = AnsiTerm::new(Vec::new());
+        self.root
+            .render_rows(rows, &mut backend, self.widths)
+            .expect("writing to memory can't fail");
+        String::from_utf8(backend.into_inner()).expect("rendered invalid UTF-8")
+    }
+    /// Render the whole document into a string, optionally capped at `max_lines` lines -
+    /// this doesn't need a terminal at all
+    pub fn render_to_string(&self, max_lines: Option<usize>) -> String {
+        let mut backend = AnsiTerm::new(Vec::new());
+        self.render_to(&mut backend, max_lines)
+            .expect("writing to memory can't fail");
+        String::from_utf8(backend.into_inner()).expect("rendered invalid UTF-8")
+    }
+    /// What of the markdown isn't rendered as it should, in order
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+    /// Fail on the first warning, for when the document must render as written
+    pub fn check(&self) -> Result<(), CatmarkError> {
+        match self.warnings.first() {
+            Some(warning) => Err(warning.clone().into()),
+            None => Ok(()),
+        }
+    }
+    /// The boxes as built from the markdown, before the layout
+    pub fn built_dom(&self) -> &DomBox<'a> {
+        &self.source
+    }
+    /// The boxes once laid out, with the lines of texts as cut
+    pub fn laid_out_dom(&self) -> &DomBox<'a> {
+        &self.root
+    }
+    /// The line where the heading with the given id starts
+    pub fn anchor_line(&self, id: &str) -> Option<usize> {
+        self.root.anchor_line(id)
+    }
+    /// The styled pieces of text making up one line of the document
+    pub fn line_spans(&self, line: usize) -> Vec<StyledStr<'_>> {
+        self.root.line_spans_with(line, self.widths)
+    }
+    /// The laid out document as a grid of styled cells, one per column and one
+    /// row per line, to draw it or parts of it without going through ANSI
+    /// sequences - the rows are all padded with unstyled spaces to the widest one
+    pub fn cells(&self) -> Vec<Vec<Cell>> {
+        let mut grid = Grid::new(self.widths);
+        self.root
+            .render_rows(0..self.height(), &mut grid, self.widths)
+            .expect("writing to memory can't fail");
+        let mut rows = grid.into_rows();
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        for row in &mut rows {
+            row.resize_with(width, Cell::default);
+        }
+        rows
+    }
+    /// Print the document on stdout, optionally capped at `max_lines` lines
+    pub fn render(&self, max_lines: Option<usize>) -> io::Result<()> {
+        let mut backend = AnsiTerm::new(io::BufWriter::new(io::stdout().lock()));
+        self.render_to(&mut backend, max_lines)?;
+        backend.into_inner().flush()
+    }
+    /// Write the document with ANSI sequences to `out`, optionally capped at
+    /// `max_lines` lines
+    pub fn write_to<W: io::Write>(
+        &self,
+        out: W,
+        max_lines: Option<usize>,
+    ) -> Result<(), CatmarkError> {
+        let mut backend = AnsiTerm::new(io::BufWriter::new(out));
+        self.render_to(&mut backend, max_lines)?;
+        backend.into_inner().flush()?;
+        Ok(())
+    }
+    /// Write the document as bare text to `out`, optionally capped at `max_lines`
+    /// lines - borders, indentation and alignment are kept with the characters only
+    pub fn write_plain<W: io::Write>(
+        &self,
+        out: W,
+        max_lines: Option<usize>,
+    ) -> Result<(), CatmarkError> {
+        let mut backend = PlainText::new(io::BufWriter::new(out));
+        self.render_to(&mut backend, max_lines)?;
+        backend.into_inner().flush()?;
+        Ok(())
+    }
+    /// Write the document as the roff source of a man page to `out`, its first
+    /// level 1 heading being the title - the man formatter does the layout
+    pub fn write_man<W: io::Write>(&self, out: W) -> Result<(), CatmarkError> {
+        roff::write_man(&self.source, io::BufWriter::new(out))?;
+        Ok(())
+    }
+    /// Write the document as a standalone HTML page to `out`, optionally capped at
+    /// `max_lines` lines - it looks like in a terminal with this background and
+    /// the palette of the options
+    pub fn write_html<W: io::Write>(
+        &self,
+        out: W,
+        max_lines: Option<usize>,
+        background: Background,
+    ) -> Result<(), CatmarkError> {
+        let mut backend = Html::new(io::BufWriter::new(out), self.palette.clone(), background);
+        backend.begin_page()?;
+        self.render_to(&mut backend, max_lines)?;
+        backend.end_page()?;
+        backend.into_inner().flush()?;
+        Ok(())
+    }
+    /// Write the document as an SVG image to `out`, optionally capped at
+    /// `max_lines` lines - it looks like in a terminal with this background and
+    /// the palette of the options
+    pub fn write_svg<W: io::Write>(
+        &self,
+        out: W,
+        max_lines: Option<usize>,
+        background: Background,
+    ) -> Result<(), CatmarkError> {
+        let out = io::BufWriter::new(out);
+        let mut backend = Svg::new(out, self.palette.clone(), background, self.widths);
+        self.render_to(&mut backend, max_lines)?;
+        backend.end_image()?;
+        backend.into_inner().flush()?;
+        Ok(())
+    }
+    /// Print the document on stdout as a piece of a longer output, e.g. a part of
+    /// a stream - like `render` but without the empty line ending it
+    pub fn render_piece(&self) -> io::Result<()> {
+        let _span = trace::span!("render");
+        let mut backend = AnsiTerm::new(io::BufWriter::new(io::stdout().lock()));
+        self.root
+            .render_piece(&mut backend, self.tidy, self.widths)?;
+        backend.into_inner().flush()
+    }
+    /// Output the document through any backend, optionally capped at `max_lines` lines
+    pub fn render_to<B: Backend>(
+        &self,
+        backend: &mut B,
+        max_lines: Option<usize>,
+    ) -> io::Result<()> {
+        let _span = trace::span!("render");
+        self.root
+            .render_tidy(backend, max_lines, self.tidy, self.widths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{CowStr, Tag};
+
+    /// The text of the rendered lines, without the ANSI sequences
+    fn plain(doc: &Document) -> String {
+        let mut out = vec![];
+        doc.write_plain(&mut out, None).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text_directly_in_a_list() {
+        let events = [
+            Event::Start(Tag::List(None)),
+            Event::Text(CowStr::Borrowed("x")),
+            Event::End(Tag::List(None)),
+        ];
+        let doc = render_events(events.into_iter(), &RenderOptions::with_width(20));
+        assert_eq!(plain(&doc).trim(), "x");
+    }
+
+    #[test]
+    fn end_without_start() {
+        let events = [
+            Event::End(Tag::List(None)),
+            Event::Text(CowStr::Borrowed("after")),
+        ];
+        let doc = render_events(events.into_iter(), &RenderOptions::with_width(20));
+        assert_eq!(plain(&doc).trim(), "after");
+    }
+
+    #[test]
+    fn tags_left_open() {
+        let events = [
+            Event::Start(Tag::BlockQuote),
+            Event::Start(Tag::Paragraph),
+            Event::Start(Tag::Emphasis),
+            Event::Text(CowStr::Borrowed("open")),
+        ];
+        let doc = render_events(events.into_iter(), &RenderOptions::with_width(20));
+        assert!(plain(&doc).contains("open"));
+    }
+
+    #[test]
+    fn filter_dropping_items() {
+        fn drop_items(event: Event) -> Option<Event> {
+            match event {
+                Event::Start(Tag::Item) | Event::End(Tag::Item) => None,
+                event => Some(event),
+            }
+        }
+        let mut opts = RenderOptions::with_width(20);
+        opts.filters.push(&drop_items);
+        let doc = Document::with_options("- a\n- b\n", &opts);
+        assert_eq!(plain(&doc).trim(), "ab");
+    }
+
+    #[test]
+    fn filter_dropping_a_start() {
+        fn drop_quotes(event: Event) -> Option<Event> {
+            match event {
+                Event::Start(Tag::BlockQuote) => None,
+                event => Some(event),
+            }
+        }
+        let mut opts = RenderOptions::with_width(20);
+        opts.filters.push(&drop_quotes);
+        let doc = Document::with_options("> a\n\nb\n", &opts);
+        assert_eq!(
+            plain(&doc).split_whitespace().collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn huge_list_start() {
+        let events = [
+            Event::Start(Tag::List(Some(5_000_000_000))),
+            Event::Start(Tag::Item),
+            Event::Text(CowStr::Borrowed("x")),
+            Event::End(Tag::Item),
+            Event::End(Tag::List(Some(5_000_000_000))),
+        ];
+        let doc = render_events(events.into_iter(), &RenderOptions::with_width(20));
+        assert_eq!(plain(&doc).trim(), "5000000000. x");
+    }
+
+    #[test]
+    fn plain_keeps_the_layout() {
+        let doc = Document::new("# Title\n\n> quoted\n\n- item\n", 20);
+        let text = plain(&doc);
+        assert!(!text.contains('\x1b'));
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "┌─────┐");
+        assert_eq!(lines[1], "│Title│");
+        assert!(lines.iter().any(|line| line.ends_with(" quoted")));
+        assert!(lines.iter().any(|line| line.ends_with(" item")));
+    }
+
+    #[test]
+    fn tables_laid_out() {
+        let text = "| a | b |\n|:--|--:|\n| long cell | 2 |\n|x|\n";
+        let doc = Document::new(text, 16);
+        let text = plain(&doc);
+        let lines: Vec<_> = text.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines[..7],
+            [
+                "┌──────────┬───┐",
+                "│ a        │ b │",
+                "├──────────┼───┤",
+                "│ long cel │ 2 │",
+                "│ l        │   │",
+                "│ x        │   │",
+                "└──────────┴───┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn tiny_widths() {
+        let text = "# Title\n\n- a\n  - b\n\n> quote\n\n<kbd>Ctrl</kbd> 漢字 `code`\n";
+        for width in 0..8 {
+            let doc = Document::with_options(text, &RenderOptions::with_width(width));
+            doc.render_to_string(None);
+        }
+    }
+
+    #[test]
+    fn nested_margins_collapse() {
+        let opts = RenderOptions::with_width(20);
+        let doc = Document::with_options("- a\n  - b\n    - c\n\nafter\n", &opts);
+        let text = plain(&doc);
+        assert!(text.contains("c\n\nafter"), "{:?}", text);
+        let doc = Document::with_options("> a\n> > b\n> > > c\n\nafter\n", &opts);
+        let text = plain(&doc);
+        assert!(text.contains("c\n\nafter"), "{:?}", text);
+    }
+
+    #[test]
+    fn huge_margins() {
+        let margins = Margins {
+            left: u16::MAX,
+            right: u16::MAX,
+            max_width: Some(10),
+        };
+        assert_eq!(margins.edges(80), (u16::MAX, u16::MAX));
+    }
+
+    #[test]
+    fn no_room_between_huge_margins() {
+        let mut opts = RenderOptions::with_width(80);
+        opts.margins.left = u16::MAX;
+        opts.margins.right = u16::MAX;
+        assert!(matches!(
+            Document::try_with_options("text", &opts),
+            Err(CatmarkError::WidthTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn errors_located() {
+        let opts = RenderOptions {
+            section: Some("missing".to_string()),
+            ..RenderOptions::with_width(80)
+        };
+        let text = "# A\n\ntext\n\n## B\n";
+        match Document::try_with_options(text, &opts) {
+            Err(CatmarkError::SectionNotFound { headings, .. }) => {
+                assert_eq!(headings, [0..4, 11..16]);
+            }
+            _ => panic!("the section was found"),
+        }
+        let doc = Document::new("text[^x]\n", 80);
+        match doc.check() {
+            Err(CatmarkError::Warning(warning)) => assert_eq!(warning.range, 4..8),
+            _ => panic!("no warning"),
+        }
+        assert!(Document::new(text, 80).check().is_ok());
+    }
+
+    #[cfg(feature = "syntax-highlighting")]
+    #[test]
+    fn syntaxes_not_found() {
+        assert!(matches!(
+            load_syntaxes(&["/nonexistent"]),
+            Err(CatmarkError::Loading(_))
+        ));
+    }
+
+    #[test]
+    fn svg_cells_as_laid_out() {
+        let mut opts = RenderOptions::with_width(20);
+        opts.widths.ambiguous_wide = true;
+        let doc = Document::with_options("①", &opts);
+        let mut out = vec![];
+        doc.write_svg(&mut out, None, Background::Dark).unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.contains("textLength=\"16.8\""), "{}", svg);
+    }
+
+    #[test]
+    fn windows_line_endings() {
+        let opts = RenderOptions::with_width(20);
+        let mut doc = Document::with_options("\u{feff}a\r\nb\r\n\r\nc\r\n", &opts);
+        assert_eq!(plain(&doc).trim(), "a b\n\nc");
+        doc.update("\u{feff}a\r\nb\r\n\r\nd\r\n", &opts);
+        assert_eq!(plain(&doc).trim(), "a b\n\nd");
+    }
+
+    #[test]
+    fn a_row_of_cells_per_line() {
+        let doc = Document::new("# Title\n\ntext\n\n- a\n- b\n", 20);
+        let cells = doc.cells();
+        assert_eq!(cells.len(), doc.height());
+        let rows: Vec<String> = cells
+            .iter()
+            .map(|row| row.iter().map(|cell| &*cell.symbol).collect())
+            .collect();
+        assert!(rows.iter().any(|row| row.contains("Title")));
+    }
+
+    #[test]
+    fn capped_output() {
+        let doc = Document::new("a\n\nb\n\nc\n", 20);
+        let mut out = vec![];
+        doc.write_plain(&mut out, Some(0)).unwrap();
+        assert!(out.is_empty());
+        let mut out = vec![];
+        doc.write_plain(&mut out, Some(2)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("a\n… "), "{:?}", text);
+    }
+
+    #[test]
+    fn code_label_on_the_code_background() {
+        let doc = Document::new("```rust\nfn x() {}\n```\n", 20);
+        let cells = doc.cells();
+        let row = cells
+            .iter()
+            .find(|row| row.iter().any(|cell| cell.symbol == "r"))
+            .unwrap();
+        let code_bg = &row[0].style.bg;
+        assert!(code_bg.is_set());
+        for cell in row
+            .iter()
+            .take_while(|cell| cell.symbol != " " || cell.style.bg.is_set())
+        {
+            assert_eq!(&cell.style.bg, code_bg);
+        }
+    }
+
+    #[test]
+    fn update_as_laid_out_anew() {
+        let opts = RenderOptions::with_width(20);
+        let (old, new) = (
+            "# Title\n\nfirst\n\nsecond\n",
+            "# Title\n\nfirst\n\nsecond, longer than the line\n",
+        );
+        let mut doc = Document::with_options(old, &opts);
+        let changed = doc.update(new, &opts);
+        let fresh = Document::with_options(new, &opts);
+        assert_eq!(plain(&doc), plain(&fresh));
+        let line_of = |text: &str| {
+            (0..fresh.height()).find(|&line| {
+                let spans = fresh.line_spans(line);
+                spans.iter().any(|span| span.text.contains(text))
+            })
+        };
+        let (first, second) = (line_of("first").unwrap(), line_of("second").unwrap());
+        // the blocks kept aren't drawn again
+        assert!(changed.iter().all(|&line| line > first));
+        assert!(changed.contains(&second) && changed.contains(&(second + 1)));
+    }
+
+    #[test]
+    fn linear_layout() {
+        // the quickest of some renders, not to count a busy moment
+        fn time(text: &str) -> std::time::Duration {
+            let runs = (0..3).map(|_| {
+                let start = std::time::Instant::now();
+                let doc = Document::new(text, 40);
+                doc.render_rows(0..doc.height());
+                start.elapsed()
+            });
+            runs.min().unwrap()
+        }
+        let breaks = |n| format!("*{}end*\n", "ab  \n".repeat(n));
+        let list = |n| {
+            (0..n)
+                .map(|i| format!("- item {}\n", i))
+                .collect::<String>()
+        };
+        for text in [breaks as fn(usize) -> String, list] {
+            let (small, big) = (time(&text(1000)), time(&text(4000)));
+            // 4 times as long for 4 times as big, 16 if it were quadratic
+            assert!(big < small * 9, "{:?} then {:?}", small, big);
+        }
+    }
+
+    #[test]
+    fn inline_footnotes_referencing_themselves() {
+        let opts = RenderOptions {
+            inline_footnotes: true,
+            ..RenderOptions::with_width(60)
+        };
+        let text = "x[^a] y[^b]\n\n[^a]: see [^a]\n\n[^b]: see [^c]\n\n[^c]: back [^b]\n";
+        let doc = Document::with_options(text, &opts);
+        let text = plain(&doc);
+        assert!(text.starts_with("x [see ¹] y [see "), "{}", text);
+        assert!(text.contains("[back ²]]"), "{}", text);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let quotes = format!("{} deep\n", ">".repeat(2000));
+        let list: String = (0..2000)
+            .map(|i| format!("{}- item {}\n", "  ".repeat(i), i))
+            .collect();
+        for (text, last) in [(quotes, "deep"), (list, "item 1999")] {
+            let doc = Document::new(&text, 80);
+            let text = plain(&doc);
+            assert!(text.contains(last), "{}", text);
+        }
+    }
+
+    #[test]
+    fn links_to_headings() {
+        let doc = Document::new("# A\n\n## Again!\n\n## Again\n\n[to](#again-1)\n", 40);
+        assert_eq!(doc.anchor_line("again"), Some(3));
+        assert_eq!(doc.anchor_line("again-1"), Some(5));
+        assert!(plain(&doc).contains("to → §3 Again"), "{}", plain(&doc));
+    }
+
+    #[test]
+    fn headings_numbered() {
+        let opts = RenderOptions {
+            number_headings: true,
+            ..RenderOptions::with_width(40)
+        };
+        let doc = Document::with_options("## A\n\n### B\n\n### C\n\n## D\n\n#### E\n", &opts);
+        let text = plain(&doc);
+        for title in ["1 A", "1.1 B", "1.2 C", "2 D", "2.0.1 E"] {
+            assert!(text.contains(title), "{} in {}", title, text);
+        }
+    }
+
+    #[test]
+    fn only_the_section() {
+        let opts = RenderOptions {
+            section: Some("set up".to_string()),
+            ..RenderOptions::with_width(40)
+        };
+        let text = "# Intro\n\nhello[^1]\n\n## Set-up\n\nsteps[^2]\n\n### Linux\n\napt\n\n\
+                    ## Usage\n\nrun\n\n[^1]: one\n\n[^2]: two\n";
+        let text = plain(&Document::with_options(text, &opts));
+        for kept in ["Set-up", "steps", "Linux", "apt", "two"] {
+            assert!(text.contains(kept), "{} in {}", kept, text);
+        }
+        for dropped in ["Intro", "hello", "Usage", "run", "one"] {
+            assert!(!text.contains(dropped), "{} in {}", dropped, text);
+        }
+    }
+
+    #[test]
+    fn alerts_titled() {
+        let doc = Document::new("> [!Warning]\n> Be careful.\n\n> [!nope]\n> quoted\n", 40);
+        let text = plain(&doc);
+        assert!(text.contains("⚠ Warning"), "{}", text);
+        assert!(!text.contains("[!Warning]"), "{}", text);
+        assert!(text.contains("[!nope]"), "{}", text);
+    }
+
+    #[test]
+    fn sup_and_sub_scripted() {
+        let text = "x<sup>2</sup> H<sub>2</sub>O E=mc<sup>x+y</sup> a<sub>β</sub>\n";
+        let text = plain(&Document::new(text, 40));
+        assert_eq!(text.trim_end(), "x² H₂O E=mcˣ⁺ʸ a_β");
+    }
+
+    #[test]
+    fn keycaps_styled() {
+        let opts = RenderOptions::with_width(40);
+        let doc = Document::with_options("Press <kbd>Ctrl</kbd>+<kbd>C</kbd>\n", &opts);
+        let keycap = &opts.stylesheet.kbd.bg;
+        assert!(keycap.is_set());
+        let row: Vec<_> = doc.cells()[0]
+            .iter()
+            .take(16)
+            .map(|cell| (cell.symbol.clone(), &cell.style.bg == keycap))
+            .collect();
+        let expected: Vec<_> = "Press  Ctrl + C "
+            .chars()
+            .enumerate()
+            .map(|(i, c)| (c.to_string(), (6..12).contains(&i) || (13..16).contains(&i)))
+            .collect();
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn tidied_lines() {
+        let text = "a&#32;&#32;\n\nb\n";
+        let mut opts = RenderOptions::with_width(20);
+        opts.spacing.paragraph = 3;
+        let doc = Document::with_options(text, &opts);
+        assert_eq!(
+            doc.render_to_string(None),
+            "a  \n   \n   \n   \nb  \n   \n   \n   \n\n"
+        );
+        opts.tidy = Tidy {
+            trim_trailing: true,
+            max_blank_lines: Some(1),
+        };
+        let doc = Document::with_options(text, &opts);
+        assert_eq!(doc.render_to_string(None), "a\n\nb\n\n\n");
+    }
+}
@@ -4,37 +4,752 @@
 
 //! Markdown (CommonMark) ANSI renderer.
 
-mod ansi_renderer;
-mod dombox;
-mod xy;
-
-use pulldown_cmark::{Options, Parser};
+use catmark::dombox::{BorderSet, BorderType, ClusterWidth, Overflow};
+#[cfg(feature = "syntax-highlighting")]
+use catmark::syntect::highlighting::ThemeSet;
+use catmark::{
+    Background, Document, LinkStyle, Margins, Preset, QuoteStyle, RenderOptions, RuleStyle,
+    Spacing, Stats, Tidy, DEFAULT_COLS,
+};
 
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "terminal")]
+fn terminal_width() -> Option<u16> {
+    term_size::dimensions().map(|(w, _)| w as u16)
+}
+
+#[cfg(not(feature = "terminal"))]
+fn terminal_width() -> Option<u16> {
+    None
+}
+
+#[cfg(feature = "terminal")]
+fn terminal_height() -> Option<usize> {
+    term_size::dimensions().map(|(_, h)| h)
+}
+
+#[cfg(not(feature = "terminal"))]
+fn terminal_height() -> Option<usize> {
+    None
+}
+
+#[cfg(feature = "terminal")]
+fn terminal_background() -> Option<Background> {
+    catmark::terminal::background()
+}
+
+#[cfg(not(feature = "terminal"))]
+fn terminal_background() -> Option<Background> {
+    None
+}
+
+/// The text of a file in UTF-8, UTF-16 with a byte order mark, or else Latin-1
+fn decode(bytes: Vec<u8>) -> String {
+    let utf16 = |bytes: &[u8], unit: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| unit([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes.get(..2) {
+        Some([0xff, 0xfe]) => utf16(&bytes[2..], u16::from_le_bytes),
+        Some([0xfe, 0xff]) => utf16(&bytes[2..], u16::from_be_bytes),
+        _ => String::from_utf8(bytes).unwrap_or_else(|err| {
+            eprintln!("catmark: input isn't UTF-8, reading it as Latin-1");
+            err.into_bytes().into_iter().map(char::from).collect()
+        }),
+    }
+}
+
+/// Files at least this big are mapped in memory rather than read
+#[cfg(all(feature = "mmap", unix))]
+const MMAP_MIN_SIZE: u64 = 1 << 20;
 
-pub const DEFAULT_COLS: u16 = 80;
+/// A file mapped in memory, read-only: its text can then be borrowed all the way
+/// to the rendered boxes without being copied
+#[cfg(all(feature = "mmap", unix))]
+struct Mapped {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Mapped {
+    /// Map the whole file, if it's a big enough regular one
+    fn new(file: &File) -> Option<Mapped> {
+        use std::os::unix::io::AsRawFd;
+
+        let meta = file.metadata().ok()?;
+        if !meta.is_file() || meta.len() < MMAP_MIN_SIZE {
+            return None;
+        }
+        let len = usize::try_from(meta.len()).ok()?;
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return None;
+        }
+        Some(Mapped { addr, len })
+    }
+    /// The content of the file - which mustn't be truncated meanwhile
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
 
-fn render_ansi(text: &str, width: u16) {
-    let p = Parser::new_ext(&text, Options::all());
-    ansi_renderer::push_ansi(p, width.into());
+/// How the rendered document is written
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    /// With ANSI sequences, for a terminal
+    Ansi,
+    /// As bare text, without any escape sequence
+    Plain,
+    /// As the roff source of a man page
+    Man,
+    /// As a standalone HTML page looking like the terminal
+    Html,
+    /// As an SVG image of the terminal
+    Svg,
+}
+
+/// What to print for a whole input
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Output {
+    /// The rendered document, optionally capped at a number of lines - then
+    /// what isn't rendered as it should on stderr, if `verbose`. The formats
+    /// showing it outside of a terminal look like one with this background.
+    Document {
+        max_lines: Option<usize>,
+        verbose: bool,
+        format: Format,
+        background: Background,
+    },
+    /// Figures about the document
+    Stats,
+    /// Its boxes in JSON, as built or laid out
+    #[cfg(feature = "dump")]
+    Dom { laid_out: bool },
+    /// The document with its boxes outlined, then the list of them
+    Outline,
+}
+
+/// Render the whole input at once
+fn render_all(bytes: Vec<u8>, opts: &RenderOptions, output: Output) {
+    render_text(&decode(bytes), opts, output);
+}
+
+/// Render a whole decoded input at once
+fn render_text(input: &str, opts: &RenderOptions, output: Output) {
+    let input = catmark::clean_input(input);
+    let (max_lines, verbose, format, background) = match output {
+        Output::Document {
+            max_lines,
+            verbose,
+            format,
+            background,
+        } => (max_lines, verbose, format, background),
+        Output::Outline => {
+            let doc = Document::with_options(&input, opts);
+            doc.render(None).expect("unable to write output");
+            let tree = doc.laid_out_dom().box_tree();
+            write!(io::stdout(), "{}", tree).expect("unable to write output");
+            return;
+        }
+        Output::Stats => {
+            let stats = Stats::new(&input, opts);
+            write!(io::stdout(), "{}", stats).expect("unable to write output");
+            return;
+        }
+        #[cfg(feature = "dump")]
+        Output::Dom { laid_out } => {
+            let doc = Document::with_options(&input, opts);
+            let dom = if laid_out {
+                doc.laid_out_dom()
+            } else {
+                doc.built_dom()
+            };
+            let mut out = io::stdout().lock();
+            serde_json::to_writer_pretty(&mut out, dom).expect("unable to write output");
+            writeln!(out).expect("unable to write output");
+            return;
+        }
+    };
+    let doc = Document::try_with_options(&input, opts).unwrap_or_else(|err| {
+        eprintln!("catmark: {}", err);
+        process::exit(1);
+    });
+    match format {
+        Format::Ansi => doc.render(max_lines).expect("unable to write output"),
+        Format::Plain => doc
+            .write_plain(io::stdout().lock(), max_lines)
+            .unwrap_or_else(|err| {
+                eprintln!("catmark: {}", err);
+                process::exit(1);
+            }),
+        Format::Man => doc.write_man(io::stdout().lock()).unwrap_or_else(|err| {
+            eprintln!("catmark: {}", err);
+            process::exit(1);
+        }),
+        Format::Html => doc
+            .write_html(io::stdout().lock(), max_lines, background)
+            .unwrap_or_else(|err| {
+                eprintln!("catmark: {}", err);
+                process::exit(1);
+            }),
+        Format::Svg => doc
+            .write_svg(io::stdout().lock(), max_lines, background)
+            .unwrap_or_else(|err| {
+                eprintln!("catmark: {}", err);
+                process::exit(1);
+            }),
+    }
+    if verbose {
+        for warning in doc.warnings() {
+            eprintln!("catmark: line {}: {}", warning.line(&input), warning.kind);
+        }
+    }
+}
+
+/// Render the input as it arrives, each top-level block once the next one
+/// starts - link definitions, footnotes and links to headings only work within
+/// the blocks rendered together. When following, the end of the input is only
+/// where to wait for more, and the blocks there are rendered once nothing
+/// could continue them.
+fn stream<R: BufRead>(mut input: R, opts: &RenderOptions, follow: bool) {
+    let mut line = vec![];
+    input
+        .read_until(b'\n', &mut line)
+        .expect("unable to read input");
+    if line.starts_with(&[0xff, 0xfe]) || line.starts_with(&[0xfe, 0xff]) {
+        // UTF-16 can't be read line by line
+        input.read_to_end(&mut line).expect("unable to read input");
+        let output = Output::Document {
+            max_lines: None,
+            verbose: false,
+            format: Format::Ansi,
+            background: Background::Dark,
+        };
+        return render_all(line, opts, output);
+    }
+    let mut pending = String::new();
+    let mut latin1 = false;
+    let mut out = io::stdout();
+    loop {
+        let end = line.is_empty();
+        // once a line isn't UTF-8, the whole input is taken as Latin-1
+        match String::from_utf8(line) {
+            Ok(text) if !latin1 => pending.push_str(&text),
+            Ok(text) => pending.extend(text.into_bytes().into_iter().map(char::from)),
+            Err(err) => {
+                if !latin1 {
+                    eprintln!("catmark: input isn't UTF-8, reading it as Latin-1");
+                    latin1 = true;
+                }
+                pending.extend(err.into_bytes().into_iter().map(char::from));
+            }
+        }
+        let complete = match (end, follow) {
+            (true, false) => pending.len(),
+            // done with the last block if a new paragraph would start after it
+            (true, true) if pending.ends_with("\n\n") => {
+                let next = format!("{}x\n", pending);
+                if catmark::complete_blocks(&next, opts) == pending.len() {
+                    pending.len()
+                } else {
+                    0
+                }
+            }
+            _ => catmark::complete_blocks(&pending, opts),
+        };
+        if complete > 0 {
+            let text = catmark::clean_input(&pending[..complete]);
+            let doc = Document::with_options(&text, opts);
+            if end && !follow {
+                doc.render(None)
+            } else {
+                doc.render_piece()
+            }
+            .expect("unable to write output");
+            out.flush().expect("unable to write output");
+            pending.drain(..complete);
+        }
+        if end {
+            if !follow {
+                break;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        line = vec![];
+        input
+            .read_until(b'\n', &mut line)
+            .expect("unable to read input");
+    }
+}
+
+/// Show the file on the whole screen, redrawing what changes whenever it's
+/// written to, until interrupted
+fn watch(path: &str, opts: &RenderOptions) {
+    let read = || decode(fs::read(path).expect("unable to read file"));
+    let modified = || fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let text = read();
+    let text = catmark::clean_input(&text);
+    let mut doc = Document::with_options(&text, opts);
+    let mut lines: Vec<usize> = (0..doc.height()).collect();
+    let mut last = modified();
+    let mut out = io::stdout();
+    print!("\x1b[2J");
+    loop {
+        // what's below the screen can't be redrawn in place
+        if let Some(rows) = terminal_height() {
+            lines.retain(|&line| line < rows);
+        }
+        print!("{}", doc.render_updates(&lines, 0));
+        out.flush().expect("unable to write output");
+        loop {
+            thread::sleep(Duration::from_millis(200));
+            let now = modified();
+            if now != last {
+                last = now;
+                break;
+            }
+        }
+        let text = read();
+        lines = doc.update(&catmark::clean_input(&text), opts);
+    }
+}
+
+/// Options from the configuration file, as if they were given first on the
+/// command line - each `name = value` line becomes `--name value`
+fn config_args() -> Vec<String> {
+    let path = match env::var_os("CATMARK_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let dir = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+                (Some(dir), _) => PathBuf::from(dir),
+                (None, Some(home)) => PathBuf::from(home).join(".config"),
+                (None, None) => return vec![],
+            };
+            dir.join("catmark").join("config")
+        }
+    };
+    let Ok(config) = fs::read_to_string(&path) else {
+        return vec![];
+    };
+    catmark::parse_config(&config).unwrap_or_else(|err| {
+        eprintln!("catmark: {}: {}", path.display(), err);
+        process::exit(1);
+    })
 }
 
 pub fn main() {
-    let mut input = String::new();
-    let mut width = DEFAULT_COLS;
-    if let Some((w, _)) = term_size::dimensions() {
-        width = w as u16;
-    }
-    if let Some(arg1) = env::args().nth(1) {
-        let mut f = File::open(arg1).expect("unable to open file");
-        f.read_to_string(&mut input).expect("unable to read file");
+    // the same bytes for the same input and arguments, e.g. for golden-file tests:
+    // nothing comes from the terminal, the environment or the configuration file
+    let deterministic = env::args().any(|arg| arg == "--deterministic");
+    let width = match deterministic {
+        true => DEFAULT_COLS,
+        false => terminal_width().unwrap_or(DEFAULT_COLS),
+    };
+    let mut max_lines = None;
+    let mut stats = false;
+    let mut dump = None;
+    let mut debug_layout = false;
+    let mut verbose = false;
+    let mut format = Format::Ansi;
+    let mut wrap = true;
+    let mut theme_file = None;
+    let mut palette_file = None;
+    let mut syntax_dirs = vec![];
+    let mut lang_aliases = vec![];
+    let mut code_full_width = false;
+    let mut code_nowrap = false;
+    let mut code_min_width = None;
+    let mut background = None;
+    let mut preset = None;
+    let mut inline_footnotes = false;
+    let mut link_style = LinkStyle::default();
+    let mut skip_anchor_links = false;
+    let mut skip_mailto_links = false;
+    let mut link_base = None;
+    let mut show_heading_ids = false;
+    let mut number_headings = false;
+    let mut section = None;
+    let mut math = false;
+    let mut highlight = false;
+    let mut search = None;
+    let mut grep = None;
+    let mut task_markers = None;
+    let mut strike_done_tasks = false;
+    let mut quote_style = QuoteStyle::default();
+    let mut rule_style = RuleStyle::default();
+    let mut rule_char = None;
+    let mut borders = None;
+    let mut bullets = None;
+    let mut spacing = Spacing::default();
+    let mut margins = Margins::default();
+    let mut center_headings = false;
+    let mut quote_tint = false;
+    let mut hyphenate = false;
+    let mut ambiguous_wide = false;
+    let mut cluster_width = ClusterWidth::default();
+    let mut tidy = Tidy::default();
+    let mut emoji = true;
+    let mut streamed = false;
+    let mut follow = false;
+    let mut watching = false;
+    let mut path = None;
+    let config = if deterministic { vec![] } else { config_args() };
+    let mut args = config.into_iter().chain(env::args().skip(1));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" | "--max-lines" => {
+                let n = args.next().expect("missing number of lines");
+                max_lines = Some(n.parse().expect("invalid number of lines"));
+            }
+            "--no-wrap" => wrap = false,
+            "--deterministic" => {}
+            "--stats" => stats = true,
+            "--dump-dom" => dump = Some(false),
+            "--dump-layout" => dump = Some(true),
+            "--debug-layout" => debug_layout = true,
+            "-v" | "--verbose" => verbose = true,
+            "--format" => {
+                format = match args.next().expect("missing output format").as_str() {
+                    "ansi" => Format::Ansi,
+                    "plain" => Format::Plain,
+                    "man" => Format::Man,
+                    "html" => Format::Html,
+                    "svg" => Format::Svg,
+                    _ => panic!("output format must be ansi, plain, man, html or svg"),
+                }
+            }
+            "--theme-file" => theme_file = Some(args.next().expect("missing theme file")),
+            "--palette" => palette_file = Some(args.next().expect("missing palette file")),
+            "--syntax-dir" => syntax_dirs.push(args.next().expect("missing syntax directory")),
+            "--code-full-width" => code_full_width = true,
+            "--code-nowrap" => code_nowrap = true,
+            "--code-min-width" => {
+                let n = args.next().expect("missing code block width");
+                code_min_width = Some(n.parse().expect("invalid code block width"));
+            }
+            "--inline-footnotes" => inline_footnotes = true,
+            "--links" => {
+                link_style = match args.next().expect("missing link style").as_str() {
+                    "inline" => LinkStyle::Inline,
+                    "reference" => LinkStyle::Reference,
+                    "osc8" => LinkStyle::Osc8,
+                    "hidden" => LinkStyle::Hidden,
+                    _ => panic!("link style must be inline, reference, osc8 or hidden"),
+                }
+            }
+            "--show-ids" => show_heading_ids = true,
+            "--number-headings" => number_headings = true,
+            "--math" => math = true,
+            "--highlight-marks" => highlight = true,
+            "--highlight" => search = Some(args.next().expect("missing pattern")),
+            "--grep" => grep = Some(args.next().expect("missing pattern")),
+            "--task-markers" => {
+                let markers = args.next().expect("missing task markers");
+                let (todo, done) = markers
+                    .split_once(',')
+                    .expect("task markers must be like todo,done");
+                task_markers = Some((todo.to_string(), done.to_string()));
+            }
+            "--ascii-tasks" => task_markers = Some(("[ ]".to_string(), "[x]".to_string())),
+            "--strike-done-tasks" => strike_done_tasks = true,
+            "--bullets" => {
+                let list = args.next().expect("missing bullets");
+                bullets = Some(list.split(',').map(String::from).collect());
+            }
+            "--ascii-bullets" => bullets = Some(["*", "-", "+"].map(String::from).to_vec()),
+            "--compact" => spacing = Spacing::compact(),
+            "--center-headings" => center_headings = true,
+            "--quote-tint" => quote_tint = true,
+            "--hyphenate" => hyphenate = true,
+            "--ambiguous-wide" => ambiguous_wide = true,
+            "--trim" => tidy.trim_trailing = true,
+            "--max-blank-lines" => {
+                let n = args.next().expect("missing number of blank lines");
+                tidy.max_blank_lines = Some(n.parse().expect("invalid number of blank lines"));
+            }
+            "--cluster-width" => {
+                cluster_width = match args.next().expect("missing cluster width").as_str() {
+                    "unicode" => ClusterWidth::Unicode,
+                    "terminal" => ClusterWidth::Terminal,
+                    _ => panic!("cluster width must be unicode or terminal"),
+                }
+            }
+            "--margin" => {
+                let n = args.next().expect("missing margin");
+                margins.left = n.parse().expect("invalid margin");
+                margins.right = margins.left;
+            }
+            "--max-width" => {
+                let n = args.next().expect("missing maximum width");
+                margins.max_width = Some(n.parse().expect("invalid maximum width"));
+            }
+            "--quotes" => {
+                quote_style = match args.next().expect("missing quote style").as_str() {
+                    "border" => QuoteStyle::Border,
+                    "prefix" => QuoteStyle::Prefix,
+                    _ => panic!("quote style must be border or prefix"),
+                }
+            }
+            "--rule" => {
+                let style = args.next().expect("missing rule style");
+                let (name, param) = match style.split_once(':') {
+                    Some((name, param)) => (name, Some(param)),
+                    None => (style.as_str(), None),
+                };
+                let percent = || param.map_or(50, |p| p.parse().expect("invalid rule width"));
+                rule_style = match name {
+                    "full" => RuleStyle::Full,
+                    "centered" => RuleStyle::Centered(percent()),
+                    "partial" => RuleStyle::Partial(percent()),
+                    "ornament" => RuleStyle::Ornament(param.unwrap_or("❦").to_string()),
+                    _ => panic!("rule style must be full, centered, partial or ornament"),
+                }
+            }
+            "--rule-char" => {
+                let c = args.next().expect("missing rule character");
+                let mut chars = c.chars();
+                rule_char = match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c),
+                    _ => panic!("rule character must be a single character"),
+                }
+            }
+            "--borders" => {
+                let chars = args.next().expect("missing border characters");
+                borders = Some(BorderSet::parse(&chars).expect("borders must be 11 characters"));
+            }
+            "--no-emoji" => emoji = false,
+            "--stream" => streamed = true,
+            "-f" | "--follow" => follow = true,
+            "-w" | "--watch" => watching = true,
+            "--section" => section = Some(args.next().expect("missing section name")),
+            "--link-base" => link_base = Some(args.next().expect("missing link base")),
+            "--skip-anchor-links" => skip_anchor_links = true,
+            "--skip-mailto-links" => skip_mailto_links = true,
+            "--light" => background = Some(Background::Light),
+            "--dark" => background = Some(Background::Dark),
+            "--preset" => {
+                preset = Some(match args.next().expect("missing preset").as_str() {
+                    "dark" => Preset::Dark,
+                    "light" => Preset::Light,
+                    "plain" | "notty" => Preset::Plain,
+                    "high-contrast" => Preset::HighContrast,
+                    _ => panic!("preset must be dark, light, plain or high-contrast"),
+                })
+            }
+            "--lang-alias" => {
+                let alias = args.next().expect("missing language alias");
+                let (from, to) = alias
+                    .split_once('=')
+                    .expect("language alias must be like from=to");
+                lang_aliases.push((from.to_lowercase(), to.to_string()));
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let mut opts = RenderOptions::with_width(width);
+    opts.wrap = wrap;
+    if let Some(preset) = preset {
+        background = preset.background();
+        opts.set_preset(preset);
+    } else {
+        if !deterministic {
+            background = background.or_else(terminal_background);
+        }
+        if let Some(background) = background {
+            opts.set_background(background);
+        }
+    }
+    if quote_tint && preset != Some(Preset::Plain) {
+        opts.stylesheet
+            .tint_quotes(background.unwrap_or(Background::Dark));
+    }
+    opts.lang_aliases.extend(lang_aliases);
+    opts.inline_footnotes = inline_footnotes;
+    opts.link_style = link_style;
+    opts.skip_anchor_links = skip_anchor_links;
+    opts.skip_mailto_links = skip_mailto_links;
+    opts.link_base = link_base;
+    opts.show_heading_ids = show_heading_ids;
+    opts.number_headings = number_headings;
+    opts.section = section;
+    opts.math = math;
+    opts.highlight = highlight;
+    #[cfg(feature = "search")]
+    {
+        let regex =
+            |pattern: &String| catmark::regex::Regex::new(pattern).expect("invalid search pattern");
+        opts.grep = grep.as_ref().map(regex);
+        // show what matched, unless told otherwise
+        opts.search = search.or(grep).as_ref().map(regex);
+    }
+    #[cfg(not(feature = "search"))]
+    if search.is_some() || grep.is_some() {
+        eprintln!("catmark: built without search, ignoring --highlight/--grep");
+    }
+    if let Some(task_markers) = task_markers {
+        opts.task_markers = task_markers;
+    }
+    opts.strike_done_tasks = strike_done_tasks;
+    opts.quote_style = quote_style;
+    opts.rule_style = rule_style;
+    if let Some(set) = borders {
+        opts.stylesheet.set_borders(set);
+    }
+    if let Some(c) = rule_char {
+        opts.stylesheet.rule.border_type = BorderType::Custom(c);
+    }
+    opts.spacing = spacing;
+    opts.margins = margins;
+    opts.hyphenate = hyphenate;
+    opts.widths.ambiguous_wide = ambiguous_wide;
+    opts.widths.clusters = cluster_width;
+    opts.tidy = tidy;
+    #[cfg(not(feature = "hyphenate"))]
+    if hyphenate {
+        eprintln!("catmark: built without hyphenation, ignoring --hyphenate");
+    }
+    if center_headings {
+        opts.stylesheet.center_headings();
+    }
+    if let Some(bullets) = bullets {
+        opts.bullets = bullets;
+    }
+    opts.emoji = emoji;
+    opts.stylesheet.code_block.extend |= code_full_width;
+    opts.stylesheet.code_min_width = code_min_width;
+    if code_nowrap {
+        opts.stylesheet.code_block.overflow = Overflow::Truncate;
+    }
+    if let Some(path) = palette_file {
+        let text = fs::read_to_string(&path).expect("unable to read palette file");
+        opts.palette = catmark::parse_palette(&text).unwrap_or_else(|err| {
+            eprintln!("catmark: {}: {}", path, err);
+            process::exit(1);
+        });
+    }
+    #[cfg(feature = "syntax-highlighting")]
+    let themes = theme_file.map(|path| {
+        let theme = ThemeSet::get_theme(&path).expect("unable to load theme file");
+        ThemeSet {
+            themes: [(path, theme)].into(),
+        }
+    });
+    #[cfg(feature = "syntax-highlighting")]
+    let syntaxes = if syntax_dirs.is_empty() {
+        None
+    } else {
+        Some(catmark::load_syntaxes(&syntax_dirs).expect("unable to load syntaxes"))
+    };
+    #[cfg(feature = "syntax-highlighting")]
+    {
+        opts.themes = themes.as_ref();
+        opts.syntaxes = syntaxes.as_ref();
+    }
+    #[cfg(not(feature = "syntax-highlighting"))]
+    if theme_file.is_some() || !syntax_dirs.is_empty() {
+        eprintln!("catmark: built without syntax highlighting, ignoring --theme-file/--syntax-dir");
+    }
+    #[cfg(not(feature = "dump"))]
+    if dump.is_some() {
+        eprintln!("catmark: built without dump, ignoring --dump-dom/--dump-layout");
+    }
+    opts.outline_boxes = debug_layout;
+    let output = match (stats, dump) {
+        (true, _) => Output::Stats,
+        #[cfg(feature = "dump")]
+        (false, Some(laid_out)) => Output::Dom { laid_out },
+        _ if debug_layout => Output::Outline,
+        _ => Output::Document {
+            max_lines,
+            verbose,
+            format,
+            background: background.unwrap_or(Background::Dark),
+        },
+    };
+    // a section, a line count or figures need the whole document
+    let whole = deterministic
+        || opts.section.is_some()
+        || !matches!(
+            output,
+            Output::Document {
+                max_lines: None,
+                format: Format::Ansi,
+                ..
+            }
+        );
+    // rendered in pieces, asked for: the pieces don't share their link
+    // definitions, footnotes nor heading numbers
+    let streaming = follow || (streamed && path.is_none() && !whole);
+    // the end of a stream is too late to list its links
+    if streaming && opts.link_style == LinkStyle::Reference {
+        eprintln!("catmark: streaming, the links are shown inline instead of listed");
+        opts.link_style = LinkStyle::Inline;
+    }
+    if watching {
+        return watch(&path.expect("--watch needs a file"), &opts);
+    }
+    let mut bytes = vec![];
+    if let Some(path) = path {
+        let mut f = File::open(path).expect("unable to open file");
+        if follow {
+            return stream(BufReader::new(f), &opts, true);
+        }
+        #[cfg(all(feature = "mmap", unix))]
+        if let Some(mapped) = Mapped::new(&f) {
+            // other encodings need a decoded copy anyway
+            let bytes = mapped.bytes();
+            let utf16 = bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff]);
+            match std::str::from_utf8(bytes) {
+                Ok(text) if !utf16 => return render_text(text, &opts, output),
+                _ => return render_all(bytes.to_vec(), &opts, output),
+            }
+        }
+        f.read_to_end(&mut bytes).expect("unable to read file");
+    } else if streaming {
+        return stream(io::stdin().lock(), &opts, follow);
     } else {
         io::stdin()
-            .read_to_string(&mut input)
+            .read_to_end(&mut bytes)
             .expect("unable to read stdin");
     }
-    render_ansi(&input, width);
+    render_all(bytes, &opts, output);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodings_decoded() {
+        assert_eq!(decode("héllo".into()), "héllo");
+        assert_eq!(decode(vec![0xff, 0xfe, b'h', 0, 0xe9, 0]), "hé");
+        assert_eq!(decode(vec![0xfe, 0xff, 0, b'h', 0, 0xe9]), "hé");
+        assert_eq!(decode(vec![b'h', 0xe9]), "hé");
+    }
 }
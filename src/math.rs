@@ -0,0 +1,382 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Approximation of TeX math with Unicode characters
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('0', '⁰'),
+    ('1', '¹'),
+    ('2', '²'),
+    ('3', '³'),
+    ('4', '⁴'),
+    ('5', '⁵'),
+    ('6', '⁶'),
+    ('7', '⁷'),
+    ('8', '⁸'),
+    ('9', '⁹'),
+    ('+', '⁺'),
+    ('-', '⁻'),
+    ('=', '⁼'),
+    ('(', '⁽'),
+    (')', '⁾'),
+    ('a', 'ᵃ'),
+    ('b', 'ᵇ'),
+    ('c', 'ᶜ'),
+    ('d', 'ᵈ'),
+    ('e', 'ᵉ'),
+    ('f', 'ᶠ'),
+    ('g', 'ᵍ'),
+    ('h', 'ʰ'),
+    ('i', 'ⁱ'),
+    ('j', 'ʲ'),
+    ('k', 'ᵏ'),
+    ('l', 'ˡ'),
+    ('m', 'ᵐ'),
+    ('n', 'ⁿ'),
+    ('o', 'ᵒ'),
+    ('p', 'ᵖ'),
+    ('r', 'ʳ'),
+    ('s', 'ˢ'),
+    ('t', 'ᵗ'),
+    ('u', 'ᵘ'),
+    ('v', 'ᵛ'),
+    ('w', 'ʷ'),
+    ('x', 'ˣ'),
+    ('y', 'ʸ'),
+    ('z', 'ᶻ'),
+    (' ', ' '),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('0', '₀'),
+    ('1', '₁'),
+    ('2', '₂'),
+    ('3', '₃'),
+    ('4', '₄'),
+    ('5', '₅'),
+    ('6', '₆'),
+    ('7', '₇'),
+    ('8', '₈'),
+    ('9', '₉'),
+    ('+', '₊'),
+    ('-', '₋'),
+    ('=', '₌'),
+    ('(', '₍'),
+    (')', '₎'),
+    ('a', 'ₐ'),
+    ('e', 'ₑ'),
+    ('h', 'ₕ'),
+    ('i', 'ᵢ'),
+    ('j', 'ⱼ'),
+    ('k', 'ₖ'),
+    ('l', 'ₗ'),
+    ('m', 'ₘ'),
+    ('n', 'ₙ'),
+    ('o', 'ₒ'),
+    ('p', 'ₚ'),
+    ('r', 'ᵣ'),
+    ('s', 'ₛ'),
+    ('t', 'ₜ'),
+    ('u', 'ᵤ'),
+    ('v', 'ᵥ'),
+    ('x', 'ₓ'),
+    (' ', ' '),
+];
+
+const SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "φ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("times", "×"),
+    ("cdot", "·"),
+    ("div", "÷"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("propto", "∝"),
+    ("infty", "∞"),
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("int", "∫"),
+    ("oint", "∮"),
+    ("sqrt", "√"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("emptyset", "∅"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("neg", "¬"),
+    ("wedge", "∧"),
+    ("land", "∧"),
+    ("vee", "∨"),
+    ("lor", "∨"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("iff", "⇔"),
+    ("mapsto", "↦"),
+    ("circ", "∘"),
+    ("degree", "°"),
+    ("ldots", "…"),
+    ("cdots", "⋯"),
+    ("dots", "…"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("{", "{"),
+    ("}", "}"),
+    ("$", "$"),
+    ("%", "%"),
+    (",", " "),
+    (";", " "),
+    ("quad", "  "),
+    ("qquad", "    "),
+];
+
+const BLACKBOARD: &[(char, char)] = &[
+    ('C', 'ℂ'),
+    ('N', 'ℕ'),
+    ('P', 'ℙ'),
+    ('Q', 'ℚ'),
+    ('R', 'ℝ'),
+    ('Z', 'ℤ'),
+];
+
+fn map_chars(text: &str, table: &[(char, char)]) -> Option<String> {
+    text.chars()
+        .map(|c| table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to))
+        .collect()
+}
+
+/// `text` written with superscript characters, if they all have one
+pub fn superscript(text: &str) -> Option<String> {
+    map_chars(text, SUPERSCRIPTS)
+}
+
+/// `text` written with subscript characters, if they all have one
+pub fn subscript(text: &str) -> Option<String> {
+    map_chars(text, SUBSCRIPTS)
+}
+
+/// The pieces of `text`, telling which ones are `$math$` or `$$math$$` (without
+/// their dollars) - a dollar followed by a space or a digit is just a dollar
+pub fn split(text: &str) -> Vec<(&str, bool)> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        let delim = if rest[start..].starts_with("$$") {
+            "$$"
+        } else {
+            "$"
+        };
+        let inner = &rest[start + delim.len()..];
+        let end = match inner.find(delim) {
+            Some(end) if !inner.starts_with(char::is_whitespace) => end,
+            _ => {
+                pieces.push((&rest[..start + delim.len()], false));
+                rest = inner;
+                continue;
+            }
+        };
+        let closes = !inner[..end].ends_with(char::is_whitespace)
+            && !inner[end + delim.len()..].starts_with(|c: char| c.is_ascii_digit());
+        if end == 0 || !closes {
+            pieces.push((&rest[..start + delim.len()], false));
+            rest = inner;
+            continue;
+        }
+        if start > 0 {
+            pieces.push((&rest[..start], false));
+        }
+        pieces.push((&inner[..end], true));
+        rest = &inner[end + delim.len()..];
+    }
+    if !rest.is_empty() {
+        pieces.push((rest, false));
+    }
+    pieces
+}
+
+/// Read an argument: a `{group}`, a `\command` or a single character
+fn argument(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut depth = 1;
+            let mut group = String::new();
+            for c in chars.by_ref() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                group.push(c);
+            }
+            to_unicode(&group)
+        }
+        Some('\\') => {
+            chars.next();
+            to_unicode(&format!("\\{}", command(chars)))
+        }
+        Some(_) => chars.next().unwrap().to_string(),
+        None => String::new(),
+    }
+}
+
+/// Read the name of a command, after its backslash
+fn command(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_alphabetic() {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+    if name.is_empty() {
+        name.extend(chars.next());
+    }
+    name
+}
+
+/// Parenthesize `text` if it's more than one character, for a "(a+b)/c" fraction
+pub fn operand(text: String) -> String {
+    if text.chars().count() > 1 {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+/// Unicode approximation of some TeX math, like "α²" for `\alpha^2`
+pub fn to_unicode(tex: &str) -> String {
+    let mut out = String::new();
+    let mut chars = tex.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' | '_' => {
+                let arg = argument(&mut chars);
+                let script = match c {
+                    '^' => superscript(&arg),
+                    _ => subscript(&arg),
+                };
+                match script {
+                    Some(script) => out.push_str(&script),
+                    None => {
+                        out.push(c);
+                        out.push_str(&operand(arg));
+                    }
+                }
+            }
+            '{' | '}' => {}
+            '\\' => match &command(&mut chars)[..] {
+                "frac" => {
+                    let numerator = operand(argument(&mut chars));
+                    let denominator = operand(argument(&mut chars));
+                    out.push_str(&format!("{}⁄{}", numerator, denominator));
+                }
+                "sqrt" => out.push_str(&format!("√{}", operand(argument(&mut chars)))),
+                "mathbb" => {
+                    let arg = argument(&mut chars);
+                    out.push_str(&map_chars(&arg, BLACKBOARD).unwrap_or(arg));
+                }
+                "mathrm" | "mathbf" | "mathit" | "text" | "operatorname" => {
+                    out.push_str(&argument(&mut chars))
+                }
+                "left" | "right" | "displaystyle" => {}
+                name => match SYMBOLS.iter().find(|(command, _)| *command == name) {
+                    Some((_, symbol)) => out.push_str(symbol),
+                    None => {
+                        out.push('\\');
+                        out.push_str(name);
+                    }
+                },
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tex_approximated() {
+        assert_eq!(to_unicode(r"\pi r^2"), "π r²");
+        assert_eq!(to_unicode(r"x_i + \alpha"), "xᵢ + α");
+        assert_eq!(to_unicode(r"\frac{a+b}{c} \le \sqrt{2}"), "(a+b)⁄c ≤ √2");
+        assert_eq!(
+            to_unicode(r"x^{\beta} \in \mathbb{R} \foo"),
+            "x^β ∈ ℝ \\foo"
+        );
+    }
+
+    #[test]
+    fn dollars_split() {
+        assert_eq!(
+            split("costs $5, or $x^2$ and $$y$$"),
+            [
+                ("costs $", false),
+                ("5, or ", false),
+                ("x^2", true),
+                (" and ", false),
+                ("y", true)
+            ]
+        );
+    }
+}
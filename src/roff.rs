@@ -0,0 +1,288 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Output as a man page - the boxes as built become roff macros, the layout being
+//! left to the formatter
+
+use crate::dombox::{BorderType, BoxKind, DomBox};
+use std::io;
+
+/// Writer of the roff source of a man page
+struct Man<W: io::Write> {
+    out: W,
+    /// Nothing written yet on the current line
+    bol: bool,
+    /// A macro just started a paragraph, the next text goes in it
+    fresh: bool,
+    /// Number of lists the current text is in
+    lists: usize,
+    /// Font of the text written last, as `(bold, italic)`
+    font: (bool, bool),
+}
+
+/// Write the boxes of a document as a man page in section 7 - the first level 1
+/// heading is its title
+pub fn write_man<W: io::Write>(root: &DomBox, out: W) -> io::Result<()> {
+    let heading = root
+        .children
+        .iter()
+        .find(|child| matches!(child.kind, BoxKind::Header(1, _)));
+    let name = heading.map_or("catmark".to_string(), DomBox::text);
+    let mut man = Man {
+        out,
+        bol: true,
+        fresh: false,
+        lists: 0,
+        font: (false, false),
+    };
+    writeln!(man.out, ".TH \"{}\" 7", quoted(&name.to_uppercase()))?;
+    for child in &root.children {
+        // it already titles the page
+        if !heading.is_some_and(|heading| std::ptr::eq(child, heading)) {
+            man.block(child)?;
+        }
+    }
+    man.end_paragraph()?;
+    man.out.flush()
+}
+
+impl<W: io::Write> Man<W> {
+    fn block(&mut self, dombox: &DomBox) -> io::Result<()> {
+        match dombox.kind {
+            BoxKind::Header(level, _) => {
+                self.end_paragraph()?;
+                let mac = if level <= 2 { ".SH" } else { ".SS" };
+                writeln!(self.out, "{} \"{}\"", mac, quoted(&dombox.text()))?;
+                self.fresh = true;
+            }
+            BoxKind::List(_) | BoxKind::ListBullet => self.list(dombox)?,
+            BoxKind::Table => self.table(dombox)?,
+            // only code blocks refuse hyphenation
+            BoxKind::Block if dombox.style.hyphenate == Some(false) => {
+                self.paragraph()?;
+                writeln!(self.out, ".RS 4")?;
+                writeln!(self.out, ".nf")?;
+                self.bol = true;
+                self.code(dombox)?;
+                self.end_line()?;
+                writeln!(self.out, ".fi")?;
+                writeln!(self.out, ".RE")?;
+                self.fresh = false;
+            }
+            // quotes and alerts, not the blank space inside them
+            BoxKind::Block
+                if dombox.size.border.left > 0.into()
+                    && dombox.style.border_type != BorderType::Empty =>
+            {
+                self.end_paragraph()?;
+                writeln!(self.out, ".RS 4")?;
+                self.fresh = false;
+                for child in &dombox.children {
+                    self.block(child)?;
+                }
+                self.end_paragraph()?;
+                writeln!(self.out, ".RE")?;
+            }
+            // a hard break, the paragraph goes on after it
+            BoxKind::Break => {
+                self.end_paragraph()?;
+                writeln!(self.out, ".br")?;
+                self.fresh = true;
+            }
+            BoxKind::InlineContainer => {
+                self.paragraph()?;
+                self.inline(dombox, (false, false))?;
+            }
+            _ => {
+                for child in &dombox.children {
+                    self.block(child)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// The bullets of a list tag the paragraphs of the items following them
+    fn list(&mut self, list: &DomBox) -> io::Result<()> {
+        self.end_paragraph()?;
+        if self.lists > 0 {
+            writeln!(self.out, ".RS")?;
+        }
+        self.lists += 1;
+        for child in &list.children {
+            match child.kind {
+                BoxKind::ListBullet => {
+                    self.end_paragraph()?;
+                    let bullet = child.text();
+                    let width = bullet.trim().chars().count() + 1;
+                    writeln!(self.out, ".IP \"{}\" {}", quoted(bullet.trim()), width)?;
+                    self.fresh = true;
+                }
+                _ => self.block(child)?,
+            }
+        }
+        self.lists -= 1;
+        self.end_paragraph()?;
+        if self.lists > 0 {
+            writeln!(self.out, ".RE")?;
+        }
+        Ok(())
+    }
+    /// A line per row of a table, its cells separated by bars - the columns
+    /// aren't aligned, that would take tbl
+    fn table(&mut self, table: &DomBox) -> io::Result<()> {
+        self.paragraph()?;
+        let rows = table.children.iter().map(|column| column.children.len());
+        for row in 0..rows.max().unwrap_or(0) {
+            for (i, column) in table.children.iter().enumerate() {
+                if i > 0 {
+                    self.set_font((false, false))?;
+                    self.text(" | ")?;
+                }
+                if let Some(cell) = column.children.get(row) {
+                    self.inline(cell, (false, false))?;
+                }
+            }
+            self.end_paragraph()?;
+            writeln!(self.out, ".br")?;
+        }
+        Ok(())
+    }
+    /// Start a paragraph, unless a macro just did - it's indented in a list item
+    fn paragraph(&mut self) -> io::Result<()> {
+        self.end_paragraph()?;
+        if !std::mem::take(&mut self.fresh) {
+            match self.lists {
+                0 => writeln!(self.out, ".PP")?,
+                _ => writeln!(self.out, ".IP")?,
+            }
+        }
+        Ok(())
+    }
+    fn end_paragraph(&mut self) -> io::Result<()> {
+        self.set_font((false, false))?;
+        self.end_line()
+    }
+    fn end_line(&mut self) -> io::Result<()> {
+        if !self.bol {
+            writeln!(self.out)?;
+            self.bol = true;
+        }
+        Ok(())
+    }
+    /// The texts of an inline box, in the font of `outer` plus theirs
+    fn inline(&mut self, dombox: &DomBox, outer: (bool, bool)) -> io::Result<()> {
+        let font = (outer.0 || dombox.style.bold, outer.1 || dombox.style.italic);
+        match dombox.kind {
+            BoxKind::Text(ref text) => {
+                // a space starting a line would break it
+                let text = if self.bol { text.trim_start() } else { text };
+                if dombox.style.hyphenate == Some(false) {
+                    self.set_font((true, font.1))?;
+                } else {
+                    self.set_font(font)?;
+                }
+                self.text(text)
+            }
+            BoxKind::Break => {
+                self.end_line()?;
+                writeln!(self.out, ".br")
+            }
+            _ => {
+                for child in &dombox.children {
+                    self.inline(child, font)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    /// The lines of a code block, as they are
+    fn code(&mut self, dombox: &DomBox) -> io::Result<()> {
+        match dombox.kind {
+            BoxKind::Text(ref text) => self.text(text),
+            BoxKind::Break => {
+                writeln!(self.out)?;
+                self.bol = true;
+                Ok(())
+            }
+            _ => {
+                for child in &dombox.children {
+                    self.code(child)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    fn set_font(&mut self, font: (bool, bool)) -> io::Result<()> {
+        if font == self.font {
+            return Ok(());
+        }
+        self.font = font;
+        let escape = match font {
+            (false, false) => "\\fR",
+            (true, false) => "\\fB",
+            (false, true) => "\\fI",
+            (true, true) => "\\f(BI",
+        };
+        self.bol = false;
+        write!(self.out, "{}", escape)
+    }
+    /// Some text, escaped so that roff doesn't take it for requests
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        let text = escape(text);
+        if self.bol && (text.starts_with('.') || text.starts_with('\'')) {
+            write!(self.out, "\\&")?;
+        }
+        self.bol &= text.is_empty();
+        write!(self.out, "{}", text)
+    }
+}
+
+/// Text with its backslashes escaped, and its newlines dropped
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('\n', " ")
+}
+
+/// Text made safe to put in a quoted macro argument
+fn quoted(text: &str) -> String {
+    escape(text).replace('"', "\\(dq")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Document;
+
+    fn man(text: &str) -> String {
+        let mut out = vec![];
+        Document::new(text, 80).write_man(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn table_rows() {
+        let man = man("| a | b |\n|---|---|\n| 1 | 2 |\n");
+        assert!(
+            man.ends_with(".PP\n\\fBa\\fR | \\fBb\\fR\n.br\n1 | 2\n.br\n"),
+            "{}",
+            man
+        );
+    }
+
+    #[test]
+    fn macros_of_the_blocks() {
+        let text = "# Tool\n\n## Usage\n\n- one\n- two\n\n> quoted\n";
+        assert_eq!(
+            man(text),
+            ".TH \"TOOL\" 7\n.SH \"Usage\"\n.IP \"•\" 2\none\n.IP \"•\" 2\ntwo\n\
+             .RS 4\n.PP\nquoted\n.RE\n"
+        );
+    }
+
+    #[test]
+    fn requests_escaped() {
+        let text = "Run `x` with a \\\\ path.\n\n```\n.code\n```\n";
+        let man = man(text);
+        assert!(man.contains("Run \\fBx\\fR with a \\e path.\n"), "{}", man);
+        assert!(man.contains(".nf\n\\&.code\n.fi\n"), "{}", man);
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Figures about a document: its size, outline, code and links
+
+use crate::ansi_renderer::fence_lang;
+use crate::RenderOptions;
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Words read in a minute, a common estimate for prose
+const WORDS_PER_MINUTE: usize = 200;
+
+/// What's in a markdown document
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// Words of the text outside of code blocks
+    pub words: usize,
+    /// Level and title of the headings, in order
+    pub headings: Vec<(u8, String)>,
+    /// Number of code blocks by language, "" for the ones without any
+    pub code_blocks: BTreeMap<String, usize>,
+    pub links: usize,
+    pub images: usize,
+}
+
+impl Stats {
+    /// Go through `text`, parsed as told by `opts`
+    pub fn new(text: &str, opts: &RenderOptions) -> Self {
+        let mut stats = Stats::default();
+        let mut prose = String::new();
+        let mut heading = None;
+        let mut in_code_block = false;
+        for event in Parser::new_ext(text, opts.parser_options()) {
+            match event {
+                Event::Start(Tag::Heading(..)) => heading = Some(String::new()),
+                Event::End(Tag::Heading(level, ..)) => {
+                    let title = heading.take().unwrap_or_default();
+                    stats.headings.push((level as u8, title));
+                    prose.push(' ');
+                }
+                Event::Start(Tag::CodeBlock(info)) => {
+                    let lang = match info {
+                        CodeBlockKind::Fenced(ref info) => fence_lang(info).to_lowercase(),
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    *stats.code_blocks.entry(lang).or_default() += 1;
+                    in_code_block = true;
+                }
+                Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                Event::Start(Tag::Link(..)) => stats.links += 1,
+                Event::Start(Tag::Image(..)) => stats.images += 1,
+                Event::Text(text) | Event::Code(text) if !in_code_block => {
+                    prose.push_str(&text);
+                    if let Some(ref mut title) = heading {
+                        title.push_str(&text);
+                    }
+                }
+                // texts may be cut inside words, only breaks and blocks separate them
+                Event::End(
+                    Tag::Emphasis
+                    | Tag::Strong
+                    | Tag::Strikethrough
+                    | Tag::Link(..)
+                    | Tag::Image(..),
+                ) => {}
+                Event::SoftBreak | Event::HardBreak | Event::End(_) => prose.push(' '),
+                _ => {}
+            }
+        }
+        stats.words = prose.split_whitespace().count();
+        stats
+    }
+    /// Minutes it takes to read the text, at least one if there's any
+    pub fn reading_minutes(&self) -> usize {
+        self.words.div_ceil(WORDS_PER_MINUTE)
+    }
+    pub fn code_block_count(&self) -> usize {
+        self.code_blocks.values().sum()
+    }
+}
+
+impl fmt::Display for Stats {
+    /// A summary, with the outline indented by heading level
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Words: {}", self.words)?;
+        writeln!(f, "Reading time: about {} min", self.reading_minutes())?;
+        writeln!(f, "Links: {}", self.links)?;
+        writeln!(f, "Images: {}", self.images)?;
+        write!(f, "Code blocks: {}", self.code_block_count())?;
+        let langs: Vec<_> = self
+            .code_blocks
+            .iter()
+            .map(|(lang, n)| match lang.as_str() {
+                "" => format!("{} without language", n),
+                lang => format!("{} {}", n, lang),
+            })
+            .collect();
+        if !langs.is_empty() {
+            write!(f, " ({})", langs.join(", "))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Headings: {}", self.headings.len())?;
+        let top = self.headings.iter().map(|&(level, _)| level).min();
+        for (level, title) in &self.headings {
+            let depth = usize::from(level - top.unwrap_or(1));
+            writeln!(f, "{:indent$}{}", "", title, indent = 2 + 2 * depth)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn figures_counted() {
+        let text = "# Intro *to* it\n\nSome **bold**ly said [words](x) ![pic](y.png).\n\n\
+                    ## Code\n\n```Rust\nlet skipped = 1;\n```\n\n```rust\n```\n\n    indented\n";
+        let stats = Stats::new(text, &RenderOptions::default());
+        assert_eq!(stats.words, 9);
+        assert_eq!(
+            stats.headings,
+            [(1, "Intro to it".to_string()), (2, "Code".to_string())]
+        );
+        assert_eq!(
+            stats.code_blocks,
+            BTreeMap::from([("".to_string(), 1), ("rust".to_string(), 2)])
+        );
+        assert_eq!((stats.links, stats.images), (1, 1));
+        assert_eq!(stats.code_block_count(), 3);
+        assert_eq!(stats.reading_minutes(), 1);
+        assert_eq!(Stats::default().reading_minutes(), 0);
+        let long = Stats {
+            words: 401,
+            ..Stats::default()
+        };
+        assert_eq!(long.reading_minutes(), 3);
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Styles of the various markdown elements
+
+use crate::dombox::{BorderSet, BorderType, DomColor, DomStyle, Edges, TermColor, TextAlign};
+use crate::xy::XY;
+use crate::Background;
+
+/// Just a bottom border, under the heading
+const UNDERLINE: Edges = Edges {
+    top: XY::new(0),
+    bottom: XY::new(1),
+    left: XY::new(0),
+    right: XY::new(0),
+};
+
+/// How each kind of element looks - these styles are applied over the one
+/// inherited from the enclosing element
+#[derive(Debug, Clone)]
+pub struct Stylesheet {
+    /// Paragraphs - set `align` to center or right-align their lines
+    pub paragraph: DomStyle,
+    /// `inline code`
+    pub inline_code: DomStyle,
+    /// Fenced and indented code blocks, before syntax highlighting - set `extend`
+    /// for their background to span the whole width, and `overflow` to
+    /// truncate long lines instead of wrapping them (wrapped ones are marked
+    /// as such unless `wrap_marker` is cleared)
+    pub code_block: DomStyle,
+    /// Blank space around the code, inside the background of code blocks
+    pub code_padding: Edges,
+    /// Columns taken at least by code blocks, so short snippets still look like blocks
+    pub code_min_width: Option<u16>,
+    /// The language label on top of fenced code blocks, on their background
+    /// unless it has its own
+    pub code_label: DomStyle,
+    /// `$math$`
+    pub math: DomStyle,
+    /// `<kbd>` keycaps
+    pub kbd: DomStyle,
+    /// `==highlighted==` text
+    pub mark: DomStyle,
+    /// Matches of the searched pattern, laid over the style of their text
+    pub search_match: DomStyle,
+    /// Marker of an unchecked task list item
+    pub task_todo: DomStyle,
+    /// Marker of a checked task list item
+    pub task_done: DomStyle,
+    /// Content of a checked task list item, if they're set apart
+    pub done_task: DomStyle,
+    /// Horizontal rules - their `border_type` draws the line
+    pub rule: DomStyle,
+    /// Background of blockquotes across the whole width, if set
+    pub quote_tint: DomColor,
+    /// Borders of blockquotes, by nesting level - deeper ones reuse them in turn
+    pub quotes: Vec<DomStyle>,
+    /// Headings, from H1 to H6
+    pub headings: [HeadingStyle; 6],
+    /// Tables - their `border_type` draws the frame and the rules
+    pub table: DomStyle,
+    /// The cells of the first row of tables
+    pub table_header: DomStyle,
+}
+
+/// How a heading looks: its style, plus the borders around it which also
+/// space it from what follows
+#[derive(Debug, Clone)]
+pub struct HeadingStyle {
+    /// Its `border_type` tells how the borders are drawn
+    pub style: DomStyle,
+    pub border: Edges,
+}
+
+impl HeadingStyle {
+    fn new(border_type: BorderType, border: Edges) -> Self {
+        HeadingStyle {
+            style: DomStyle {
+                fg: DomColor::from_dark(TermColor::Purple),
+                border_type,
+                ..Default::default()
+            },
+            border,
+        }
+    }
+}
+
+fn quote_style(color: TermColor, border_type: BorderType) -> DomStyle {
+    DomStyle {
+        border_fg: DomColor::from_dark(color),
+        border_type,
+        ..Default::default()
+    }
+}
+
+impl Default for Stylesheet {
+    fn default() -> Self {
+        Stylesheet {
+            paragraph: DomStyle::default(),
+            inline_code: DomStyle {
+                fg: DomColor::from_light(TermColor::Red),
+                bg: DomColor::from_grey(0x30),
+                ..Default::default()
+            },
+            code_block: DomStyle {
+                fg: DomColor::from_dark(TermColor::White),
+                bg: DomColor::from_dark(TermColor::Black),
+                wrap_marker: true,
+                ..Default::default()
+            },
+            code_padding: Edges {
+                left: 1.into(),
+                right: 1.into(),
+                ..Default::default()
+            },
+            code_min_width: None,
+            code_label: DomStyle {
+                fg: DomColor::from_grey(0x80),
+                italic: true,
+                ..Default::default()
+            },
+            math: DomStyle {
+                fg: DomColor::from_light(TermColor::Cyan),
+                ..Default::default()
+            },
+            kbd: DomStyle {
+                fg: DomColor::from_light(TermColor::White),
+                bg: DomColor::from_grey(0x50),
+                bold: true,
+                ..Default::default()
+            },
+            mark: DomStyle {
+                fg: DomColor::from_dark(TermColor::Black),
+                bg: DomColor::from_light(TermColor::Yellow),
+                ..Default::default()
+            },
+            search_match: DomStyle {
+                reverse: true,
+                ..Default::default()
+            },
+            task_todo: DomStyle {
+                fg: DomColor::from_light(TermColor::Yellow),
+                ..Default::default()
+            },
+            task_done: DomStyle {
+                fg: DomColor::from_light(TermColor::Green),
+                ..Default::default()
+            },
+            done_task: DomStyle {
+                fg: DomColor::from_grey(0x80),
+                strikethrough: true,
+                ..Default::default()
+            },
+            rule: DomStyle {
+                fg: DomColor::from_dark(TermColor::Yellow),
+                border_type: BorderType::Thin,
+                ..Default::default()
+            },
+            quote_tint: DomColor::default(),
+            quotes: vec![
+                quote_style(TermColor::Cyan, BorderType::Thin),
+                quote_style(TermColor::Blue, BorderType::Dash),
+                quote_style(TermColor::Purple, BorderType::Thin),
+            ],
+            headings: [
+                HeadingStyle::new(
+                    BorderType::Thin,
+                    Edges {
+                        top: 1.into(),
+                        bottom: 1.into(),
+                        left: 1.into(),
+                        right: 1.into(),
+                    },
+                ),
+                HeadingStyle::new(BorderType::Bold, UNDERLINE),
+                HeadingStyle::new(BorderType::Double, UNDERLINE),
+                HeadingStyle::new(BorderType::Thin, UNDERLINE),
+                HeadingStyle::new(BorderType::Dash, UNDERLINE),
+                HeadingStyle::new(BorderType::Empty, UNDERLINE),
+            ],
+            table: DomStyle {
+                border_fg: DomColor::from_grey(0x80),
+                border_type: BorderType::Thin,
+                ..Default::default()
+            },
+            table_header: DomStyle {
+                bold: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Stylesheet {
+    /// Draw all the borders with these characters, except the invisible ones and
+    /// the single character ones
+    pub fn set_borders(&mut self, set: BorderSet) {
+        let styles = self.headings.iter_mut().map(|heading| &mut heading.style);
+        let others = [&mut self.rule, &mut self.table];
+        for style in styles.chain(&mut self.quotes).chain(others) {
+            if !matches!(style.border_type, BorderType::Empty | BorderType::Custom(_)) {
+                style.border_type = BorderType::Set(set);
+            }
+        }
+    }
+    /// Center the H1 and H2 headings
+    pub fn center_headings(&mut self) {
+        for heading in &mut self.headings[..2] {
+            heading.style.align = TextAlign::Center;
+        }
+    }
+    /// Give blockquotes a subtle background, for a dark or light terminal
+    pub fn tint_quotes(&mut self, background: Background) {
+        self.quote_tint = match background {
+            Background::Dark => DomColor::from_grey(0x20),
+            Background::Light => DomColor::from_dark(TermColor::White),
+        };
+    }
+    /// Styles for terminals with a light background - the default ones suit
+    /// dark backgrounds
+    pub fn light() -> Self {
+        let mut stylesheet = Stylesheet::default();
+        stylesheet.inline_code.fg = DomColor::from_dark(TermColor::Red);
+        stylesheet.inline_code.bg = DomColor::from_dark(TermColor::White);
+        stylesheet.code_block.fg = DomColor::from_dark(TermColor::Black);
+        stylesheet.code_block.bg = DomColor::from_dark(TermColor::White);
+        stylesheet.math.fg = DomColor::from_dark(TermColor::Cyan);
+        stylesheet.kbd.fg = DomColor::from_dark(TermColor::Black);
+        stylesheet.kbd.bg = DomColor::from_grey(0xc0);
+        stylesheet.task_todo.fg = DomColor::from_dark(TermColor::Yellow);
+        stylesheet.task_done.fg = DomColor::from_dark(TermColor::Green);
+        stylesheet
+    }
+    /// Styles without any color, for monochrome terminals - attributes like bold or
+    /// reverse video still set elements apart
+    pub fn plain() -> Self {
+        let mut stylesheet = Stylesheet::default();
+        for style in stylesheet.styles_mut() {
+            style.fg = DomColor::default();
+            style.bg = DomColor::default();
+            style.border_fg = DomColor::default();
+            style.border_bg = DomColor::default();
+        }
+        stylesheet.quote_tint = DomColor::default();
+        stylesheet.inline_code.bold = true;
+        stylesheet.code_label.italic = true;
+        stylesheet.mark.reverse = true;
+        stylesheet.kbd.reverse = true;
+        stylesheet
+    }
+    /// Bright and bold styles over black, for the readers needing more contrast
+    pub fn high_contrast() -> Self {
+        let mut stylesheet = Stylesheet::default();
+        stylesheet.inline_code.fg = DomColor::from_light(TermColor::Yellow);
+        stylesheet.inline_code.bg = DomColor::from_dark(TermColor::Black);
+        stylesheet.inline_code.bold = true;
+        stylesheet.code_block.fg = DomColor::from_light(TermColor::White);
+        stylesheet.code_block.bg = DomColor::from_dark(TermColor::Black);
+        stylesheet.code_label.fg = DomColor::from_light(TermColor::White);
+        stylesheet.kbd.fg = DomColor::from_dark(TermColor::Black);
+        stylesheet.kbd.bg = DomColor::from_light(TermColor::White);
+        stylesheet.done_task.fg = DomColor::from_light(TermColor::White);
+        stylesheet.rule.fg = DomColor::from_light(TermColor::Yellow);
+        for heading in &mut stylesheet.headings {
+            heading.style.fg = DomColor::from_light(TermColor::Cyan);
+            heading.style.bold = true;
+        }
+        for quote in &mut stylesheet.quotes {
+            quote.border_fg = DomColor::from_light(TermColor::White);
+        }
+        stylesheet
+    }
+    /// All the styles, to change them at once
+    fn styles_mut(&mut self) -> impl Iterator<Item = &mut DomStyle> {
+        let headings = self.headings.iter_mut().map(|heading| &mut heading.style);
+        [
+            &mut self.paragraph,
+            &mut self.inline_code,
+            &mut self.code_block,
+            &mut self.code_label,
+            &mut self.math,
+            &mut self.kbd,
+            &mut self.mark,
+            &mut self.search_match,
+            &mut self.task_todo,
+            &mut self.task_done,
+            &mut self.done_task,
+            &mut self.rule,
+            &mut self.table,
+            &mut self.table_header,
+        ]
+        .into_iter()
+        .chain(&mut self.quotes)
+        .chain(headings)
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Questions to the terminal we're running in
+
+use crate::Background;
+
+use std::env;
+use std::io;
+use std::io::IsTerminal;
+
+/// Guess whether the terminal background is dark or light, from `$COLORFGBG`
+/// or else by asking the terminal itself
+pub fn background() -> Option<Background> {
+    from_colorfgbg().or_else(|| {
+        // don't steal the answer from a pager reading the same terminal
+        if io::stdout().is_terminal() {
+            query_background()
+        } else {
+            None
+        }
+    })
+}
+
+/// `$COLORFGBG` is like "15;0": foreground then background color index
+fn from_colorfgbg() -> Option<Background> {
+    let var = env::var("COLORFGBG").ok()?;
+    let bg: u8 = var.rsplit(';').next()?.parse().ok()?;
+    Some(match bg {
+        7 | 9..=15 => Background::Light,
+        _ => Background::Dark,
+    })
+}
+
+/// Ask the background color with an OSC 11 query, waiting a bit for the answer
+#[cfg(unix)]
+fn query_background() -> Option<Background> {
+    use std::fs::OpenOptions;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let fd = tty.as_raw_fd();
+    let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        return None;
+    }
+    let mut raw = saved;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 1; // in tenths of second
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+    let mut reply = Vec::new();
+    if tty.write_all(b"\x1b]11;?\x07").is_ok() {
+        let mut byte = [0u8];
+        // the answer ends with BEL or ST (ESC \)
+        while let Ok(1) = tty.read(&mut byte) {
+            reply.push(byte[0]);
+            if byte[0] == 7 || reply.ends_with(b"\x1b\\") || reply.len() > 64 {
+                break;
+            }
+        }
+    }
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    parse_osc11(std::str::from_utf8(&reply).ok()?)
+}
+
+#[cfg(not(unix))]
+fn query_background() -> Option<Background> {
+    None
+}
+
+/// The answer is like "\x1b]11;rgb:RRRR/GGGG/BBBB\x07", with 1 to 4 hex digits
+/// per channel
+#[cfg(unix)]
+fn parse_osc11(reply: &str) -> Option<Background> {
+    let rgb = reply.split("rgb:").nth(1)?;
+    let rgb = rgb.trim_end_matches(['\x07', '\x1b', '\\']);
+    let mut channels = rgb.split('/').map(|c| {
+        if c.is_empty() || c.len() > 4 {
+            return None;
+        }
+        let max = (1u32 << (4 * c.len())) - 1;
+        Some(u32::from_str_radix(c, 16).ok()? as f32 / max as f32)
+    });
+    let (r, g, b) = (channels.next()??, channels.next()??, channels.next()??);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    Some(if luma > 0.5 {
+        Background::Light
+    } else {
+        Background::Dark
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc11_replies() {
+        let dark = parse_osc11("\x1b]11;rgb:0000/0000/0000\x07");
+        assert_eq!(dark, Some(Background::Dark));
+        let light = parse_osc11("\x1b]11;rgb:ffff/ffff/ffff\x1b\\");
+        assert_eq!(light, Some(Background::Light));
+        assert_eq!(
+            parse_osc11("\x1b]11;rgb:e/e/e\x07"),
+            Some(Background::Light)
+        );
+        assert_eq!(parse_osc11("\x1b]11;rgb:f/f\x07"), None);
+        assert_eq!(parse_osc11("\x1b]11;rgb:fffff/0/0\x07"), None);
+        assert_eq!(parse_osc11("garbage"), None);
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Spans of the rendering stages, for the subscribers of the tracing crate with
+//! the `trace` feature - they nest, the parsing and the highlighting being in the
+//! building of the boxes; nothing is recorded without the feature
+
+/// The guard of a stage being traced, which ends when it's dropped
+#[cfg(feature = "trace")]
+pub type Span = tracing::span::EnteredSpan;
+
+#[cfg(not(feature = "trace"))]
+pub struct Span;
+
+/// Enter the span of a stage, until the returned guard is dropped
+macro_rules! span {
+    ($name:literal) => {{
+        #[cfg(feature = "trace")]
+        let span: $crate::trace::Span = tracing::debug_span!(target: "catmark", $name).entered();
+        #[cfg(not(feature = "trace"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+pub(crate) use span;
+
+/// An iterator whose items take time to come, like the events of a parser: its
+/// span is entered while each one is made
+#[cfg(feature = "trace")]
+pub struct Timed<I> {
+    pub span: tracing::Span,
+    pub iter: I,
+}
+
+#[cfg(feature = "trace")]
+impl<I: Iterator> Iterator for Timed<I> {
+    type Item = I::Item;
+    fn next(&mut self) -> Option<I::Item> {
+        let _entered = self.span.enter();
+        self.iter.next()
+    }
+}
+
+/// Trace the making of the items of an iterator as a stage, in the span current
+/// when it's called
+macro_rules! timed {
+    ($name:literal, $iter:expr) => {{
+        #[cfg(feature = "trace")]
+        let iter = $crate::trace::Timed {
+            span: tracing::debug_span!(target: "catmark", $name),
+            iter: $iter,
+        };
+        #[cfg(not(feature = "trace"))]
+        let iter = $iter;
+        iter
+    }};
+}
+pub(crate) use timed;
+
+#[cfg(all(test, feature = "trace"))]
+mod tests {
+    use std::sync::Mutex;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata};
+
+    /// The spans made, with the one they were made in
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<(&'static str, Option<&'static str>)>>,
+        entered: Mutex<Vec<u64>>,
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            let parent = self.entered.lock().unwrap().last().map(|&id| id as usize);
+            let parent = parent.map(|id| spans[id - 1].0);
+            spans.push((span.metadata().name(), parent));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event) {}
+        fn enter(&self, span: &Id) {
+            self.entered.lock().unwrap().push(span.into_u64());
+        }
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn stages_nested() {
+        let recorder = std::sync::Arc::new(Recorder::default());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            crate::Document::new("# Title\n\ntext\n", 80).render_to_string(None);
+        });
+        let spans = recorder.spans.lock().unwrap();
+        assert!(spans.contains(&("parse", Some("dom-build"))), "{:?}", spans);
+        assert!(spans.contains(&("layout", None)), "{:?}", spans);
+        assert!(spans.contains(&("render", None)), "{:?}", spans);
+    }
+}
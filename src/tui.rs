@@ -0,0 +1,162 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! ratatui widget for rendering a markdown document into a TUI buffer, and its
+//! conversion into ratatui text
+
+use crate::dombox::{DomColor, DomStyle};
+use crate::Document;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{StatefulWidget, Widget};
+
+impl From<&DomStyle> for Style {
+    fn from(dstyle: &DomStyle) -> Self {
+        let mut style = Style::default();
+        let color = |color: &DomColor| match *color {
+            DomColor::Inherited => None,
+            DomColor::Terminal => Some(Color::Reset),
+            DomColor::Indexed(idx) => Some(Color::Indexed(idx)),
+        };
+        if let Some(fg) = color(&dstyle.fg) {
+            style = style.fg(fg);
+        }
+        if let Some(bg) = color(&dstyle.bg) {
+            style = style.bg(bg);
+        }
+        if dstyle.bold {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        if dstyle.underline {
+            style = style.add_modifier(Modifier::UNDERLINED);
+        }
+        if dstyle.strikethrough {
+            style = style.add_modifier(Modifier::CROSSED_OUT);
+        }
+        if dstyle.italic {
+            style = style.add_modifier(Modifier::ITALIC);
+        }
+        if dstyle.reverse {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        style
+    }
+}
+
+/// Scrolling state of a `MarkdownView`
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MarkdownState {
+    /// First document line shown at the top of the widget
+    pub offset: usize,
+}
+
+impl MarkdownState {
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_add(lines);
+    }
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_sub(lines);
+    }
+    /// Scroll to the heading an intra-document link like "#usage" points to, telling
+    /// if there's one
+    pub fn scroll_to_anchor(&mut self, doc: &Document, anchor: &str) -> bool {
+        match doc.anchor_line(anchor.trim_start_matches('#')) {
+            Some(line) => {
+                self.offset = line;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A widget showing a laid out document - lay it out at the widget width
+/// (see `Document::relayout`) for the best result, it's clipped otherwise
+pub struct MarkdownView<'d, 'a> {
+    doc: &'d Document<'a>,
+}
+
+impl<'d, 'a> MarkdownView<'d, 'a> {
+    pub fn new(doc: &'d Document<'a>) -> Self {
+        MarkdownView { doc }
+    }
+}
+
+impl Widget for MarkdownView<'_, '_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut state = MarkdownState::default();
+        StatefulWidget::render(self, area, buf, &mut state);
+    }
+}
+
+impl StatefulWidget for MarkdownView<'_, '_> {
+    type State = MarkdownState;
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut MarkdownState) {
+        let height = self.doc.height();
+        // don't scroll past the last screenful
+        state.offset = state.offset.min(height.saturating_sub(area.height.into()));
+        for row in 0..area.height {
+            let line = state.offset + usize::from(row);
+            if line >= height {
+                break;
+            }
+            let mut x = area.x;
+            for span in self.doc.line_spans(line) {
+                if x >= area.right() {
+                    break;
+                }
+                let (next, _) = buf.set_stringn(
+                    x,
+                    area.y + row,
+                    &span.text,
+                    (area.right() - x).into(),
+                    Style::from(&*span.style),
+                );
+                x = next;
+            }
+        }
+    }
+}
+
+impl<'a> Document<'a> {
+    /// The laid out document as ratatui lines of spans, to put it in other widgets
+    /// like a `Paragraph` scrolling it their own way - lay it out at their width,
+    /// they'd wrap the lines otherwise
+    pub fn to_ratatui_text(&self) -> Text<'_> {
+        let lines: Vec<Line> = (0..self.height())
+            .map(|line| {
+                self.line_spans(line)
+                    .into_iter()
+                    .map(|span| Span::styled(span.text, Style::from(&*span.style)))
+                    .collect()
+            })
+            .collect();
+        Text::from(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_with_the_lines_and_styles() {
+        let doc = Document::new("**bold** text\n\n- item\n", 20);
+        let text = doc.to_ratatui_text();
+        assert_eq!(text.lines.len(), doc.height());
+        let first: String = text.lines[0]
+            .spans
+            .iter()
+            .map(|span| &*span.content)
+            .collect();
+        assert_eq!(first.trim_end(), "bold text");
+        let bold = text.lines[0]
+            .spans
+            .iter()
+            .find(|span| span.content == "bold");
+        assert!(bold.unwrap().style.add_modifier.contains(Modifier::BOLD));
+    }
+}
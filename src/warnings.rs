@@ -0,0 +1,108 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! What gets rendered poorly, or not at all
+
+use crate::ansi_renderer::is_tag;
+#[cfg(feature = "syntax-highlighting")]
+use crate::ansi_renderer::{fence_lang, knows_lang};
+use crate::RenderOptions;
+#[cfg(feature = "syntax-highlighting")]
+use pulldown_cmark::CodeBlockKind;
+use pulldown_cmark::{Event, Parser, Tag};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+/// Something of the markdown that isn't rendered as it should
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// A fenced code block in a language without syntax to highlight it
+    UnknownLanguage(String),
+    /// A reference to a footnote which isn't defined
+    UnresolvedFootnote(String),
+    /// HTML shown as it's written
+    SkippedHtml(String),
+}
+
+/// A non-fatal problem found in a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Where it is, in bytes from the start of the markdown (once without its
+    /// byte order mark)
+    pub range: Range<usize>,
+    pub kind: WarningKind,
+}
+
+impl Warning {
+    /// The line of `text` where it is, from 1
+    pub fn line(&self, text: &str) -> usize {
+        let offset = self.range.start.min(text.len());
+        text.as_bytes()[..offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + 1
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::UnknownLanguage(lang) => {
+                write!(f, "no syntax to highlight \"{}\" code", lang)
+            }
+            WarningKind::UnresolvedFootnote(name) => write!(f, "undefined footnote [^{}]", name),
+            WarningKind::SkippedHtml(html) => write!(f, "HTML shown as is: {}", html.trim()),
+        }
+    }
+}
+
+/// The problems of `text` when rendered with `opts`, in order
+pub fn check(text: &str, opts: &RenderOptions) -> Vec<Warning> {
+    let mut warnings = vec![];
+    let mut definitions = HashSet::new();
+    let mut references = vec![];
+    let parser = Parser::new_ext(text, opts.parser_options());
+    for (event, range) in parser.into_offset_iter() {
+        let kind = match event {
+            #[cfg(feature = "syntax-highlighting")]
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+                let lang = fence_lang(info);
+                if lang.is_empty() || !opts.code_colors || knows_lang(lang, opts) {
+                    continue;
+                }
+                WarningKind::UnknownLanguage(lang.to_string())
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                definitions.insert(name);
+                continue;
+            }
+            Event::FootnoteReference(name) => {
+                references.push((range, name));
+                continue;
+            }
+            // the ones catmark understands
+            Event::Html(ref html)
+                if ["sup", "sub", "kbd", "/sup", "/sub", "/kbd"]
+                    .iter()
+                    .any(|name| is_tag(html, name)) =>
+            {
+                continue
+            }
+            Event::Html(html) => WarningKind::SkippedHtml(html.to_string()),
+            _ => continue,
+        };
+        warnings.push(Warning { range, kind });
+    }
+    for (range, name) in references {
+        if !definitions.contains(&name) {
+            let kind = WarningKind::UnresolvedFootnote(name.to_string());
+            warnings.push(Warning { range, kind });
+        }
+    }
+    warnings.sort_by_key(|warning| warning.range.start);
+    warnings
+}
@@ -1,22 +1,32 @@
 use newtype_ops::newtype_ops;
 use std::fmt::Display;
 
-/// Inner type for terminal coordinates - should be enough for even wide terminals
-type InnerCoord = u16;
+/// Inner type for terminal coordinates - wider than the widths, which are u16,
+/// for documents longer than 65535 lines
+type InnerCoord = u32;
 
 /// Public opaque type for terminal coordinates
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "dump", derive(serde::Serialize))]
 pub struct XY(InnerCoord);
 
 impl XY {
     pub const fn new(n: InnerCoord) -> Self {
         XY(n)
     }
+    /// `n`, or the biggest coordinate if it's bigger
+    pub fn saturating_from(n: usize) -> Self {
+        XY(n.try_into().unwrap_or(InnerCoord::MAX))
+    }
+    /// `self - other`, or zero if `other` is bigger
+    pub fn saturating_sub(self, other: XY) -> Self {
+        XY(self.0.saturating_sub(other.0))
+    }
 }
 
-impl From<InnerCoord> for XY {
-    fn from(n: InnerCoord) -> Self {
-        XY(n)
+impl From<u16> for XY {
+    fn from(n: u16) -> Self {
+        XY(n.into())
     }
 }
 
@@ -27,9 +37,9 @@ impl TryFrom<usize> for XY {
     }
 }
 
-impl Into<usize> for XY {
-    fn into(self) -> usize {
-        self.0.into()
+impl From<XY> for usize {
+    fn from(n: XY) -> Self {
+        n.0 as usize
     }
 }
 
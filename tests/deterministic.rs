@@ -0,0 +1,44 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! Golden-file test of `--deterministic`: the same bytes whatever the terminal,
+//! the environment or the configuration file - the expected output is made with
+//! the default features
+
+#![cfg(all(feature = "syntax-highlighting", feature = "emoji"))]
+
+use std::process::Command;
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/sample.md");
+const GOLDEN: &[u8] = include_bytes!("golden/sample.ansi");
+
+/// Run catmark on the sample, with some environment variables set
+fn render(vars: &[(&str, &str)]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_catmark"))
+        .args(["--deterministic", SAMPLE])
+        .envs(vars.iter().copied())
+        .output()
+        .expect("unable to run catmark");
+    assert!(output.status.success(), "{:?}", output);
+    output.stdout
+}
+
+#[test]
+fn same_as_golden() {
+    assert!(
+        render(&[]) == GOLDEN,
+        "regenerate golden/sample.ansi with catmark --deterministic"
+    );
+}
+
+#[test]
+fn environment_left_out() {
+    let vars = [
+        ("COLUMNS", "33"),
+        ("COLORFGBG", "0;15"),
+        // it's not a configuration file, reading it would fail
+        ("CATMARK_CONFIG", SAMPLE),
+    ];
+    assert!(render(&vars) == GOLDEN);
+}
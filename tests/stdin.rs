@@ -0,0 +1,50 @@
+// Copyright 2016 Xavier Bestel -  All rights reserved.
+//
+// GPL goes here
+
+//! The input piped in is rendered like the same file, unless streaming is asked
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const TEXT: &str =
+    "See [docs][d] and [^1].\n\n# A\n\n# B\n\n[d]: https://example.com\n\n[^1]: a note\n";
+
+/// Run catmark with these arguments, `TEXT` being piped in
+fn piped(args: &[&str]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_catmark"))
+        .args(args)
+        .env("COLORFGBG", "15;0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("unable to run catmark");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(TEXT.as_bytes()).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn piped_like_a_file() {
+    let path = format!("{}/stdin.md", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::write(&path, TEXT).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_catmark"))
+        .args(["--number-headings", &path])
+        .env("COLORFGBG", "15;0")
+        .output()
+        .expect("unable to run catmark");
+    let file = String::from_utf8(output.stdout).unwrap();
+    let stdin = piped(&["--number-headings"]);
+    assert_eq!(stdin, file);
+    assert!(!stdin.contains("[docs][d]"), "{}", stdin);
+    assert!(stdin.contains("2 B"), "{}", stdin);
+}
+
+#[test]
+fn streamed_when_asked() {
+    let stdin = piped(&["--stream"]);
+    assert!(stdin.contains("a note"), "{}", stdin);
+}